@@ -21,6 +21,22 @@ pub fn initialize() -> App<'static, 'static> {
                 .takes_value(false)
                 .required(false),
         )
+        .arg(
+            Arg::with_name("update_snapshots")
+                .help("Rewrites the store snapshots instead of comparing against them.")
+                .long("update-snapshots")
+                .short("u")
+                .takes_value(false)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("dump_store_on_failure")
+                .help("Writes the store state of each failed test to a JSON file in <dir>.")
+                .long("dump-store-on-failure")
+                .value_name("dir")
+                .takes_value(true)
+                .required(false),
+        )
         .arg(
             Arg::with_name("test_suites")
                 .help("Please specify the names of the test suites you would like to run.")
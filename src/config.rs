@@ -1,10 +1,25 @@
 use std::path::PathBuf;
 
+use crate::context::{UnmockedCallPolicy, UnusedMockPolicy};
+use crate::logging;
 use crate::parser;
 
 pub struct MatchstickConfig {
     pub libs_path: String,
     pub tests_path: String,
+    pub fixtures_path: String,
+    pub entity_cache: bool,
+    pub merge_store_set: bool,
+    pub unmocked_calls: UnmockedCallPolicy,
+    pub unused_mocks: UnusedMockPolicy,
+    /// Contract mock fixtures loaded before each test suite, relative to the fixtures folder.
+    pub contract_mocks: Vec<String>,
+    /// The JSON-RPC endpoint contract calls that aren't mocked are forwarded to.
+    pub rpc_endpoint: Option<String>,
+    /// The file responses of the endpoint are recorded to, in the tests folder by default.
+    pub rpc_cache: Option<String>,
+    /// The block forwarded calls are made at, the latest one if not set.
+    pub rpc_block: Option<u64>,
 }
 
 impl MatchstickConfig {
@@ -13,6 +28,15 @@ impl MatchstickConfig {
         MatchstickConfig {
             libs_path: "./node_modules".to_owned(),
             tests_path: "./tests".to_owned(),
+            fixtures_path: "./tests/fixtures".to_owned(),
+            entity_cache: false,
+            merge_store_set: true,
+            unmocked_calls: UnmockedCallPolicy::Fail,
+            unused_mocks: UnusedMockPolicy::Ignore,
+            contract_mocks: vec![],
+            rpc_endpoint: None,
+            rpc_cache: None,
+            rpc_block: None,
         }
     }
 
@@ -29,6 +53,41 @@ impl MatchstickConfig {
                 parser::extract_string_or(&matchstick_yaml, "testsFolder", config.tests_path);
             config.libs_path =
                 parser::extract_string_or(&matchstick_yaml, "libsFolder", config.libs_path);
+            // Fixtures are looked up in the tests folder, unless configured otherwise.
+            let default_fixtures_path = PathBuf::from(&config.tests_path)
+                .join("fixtures")
+                .to_string_lossy()
+                .into_owned();
+            config.fixtures_path = parser::extract_string_or(
+                &matchstick_yaml,
+                "fixturesFolder",
+                default_fixtures_path,
+            );
+            config.entity_cache =
+                parser::extract_bool_or(&matchstick_yaml, "entityCache", config.entity_cache);
+            config.merge_store_set =
+                parser::extract_bool_or(&matchstick_yaml, "mergeStoreSet", config.merge_store_set);
+            if let Some(policy) = matchstick_yaml
+                .get("unmockedCalls")
+                .and_then(|v| v.as_str())
+            {
+                config.unmocked_calls = UnmockedCallPolicy::parse(policy)
+                    .unwrap_or_else(|err| logging::critical!("(matchstick.yaml) {}", err));
+            }
+            if let Some(policy) = matchstick_yaml.get("unusedMocks").and_then(|v| v.as_str()) {
+                config.unused_mocks = UnusedMockPolicy::parse(policy)
+                    .unwrap_or_else(|err| logging::critical!("(matchstick.yaml) {}", err));
+            }
+            config.contract_mocks = parser::extract_strings(&matchstick_yaml, "contractMocks");
+            config.rpc_endpoint = matchstick_yaml
+                .get("rpcEndpoint")
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_owned());
+            config.rpc_cache = matchstick_yaml
+                .get("rpcCache")
+                .and_then(|v| v.as_str())
+                .map(|v| v.to_owned());
+            config.rpc_block = matchstick_yaml.get("rpcBlock").and_then(|v| v.as_u64());
         }
 
         config
@@ -38,6 +97,7 @@ impl MatchstickConfig {
 #[cfg(test)]
 mod config_tests {
     use crate::config::MatchstickConfig;
+    use crate::context::{UnmockedCallPolicy, UnusedMockPolicy};
 
     #[test]
     fn config_from_returns_default_values_if_no_config() {
@@ -45,6 +105,15 @@ mod config_tests {
 
         assert_eq!(config.libs_path, "./node_modules".to_owned());
         assert_eq!(config.tests_path, "./tests".to_owned());
+        assert_eq!(config.fixtures_path, "./tests/fixtures".to_owned());
+        assert!(!config.entity_cache);
+        assert!(config.merge_store_set);
+        assert_eq!(config.unmocked_calls, UnmockedCallPolicy::Fail);
+        assert_eq!(config.unused_mocks, UnusedMockPolicy::Ignore);
+        assert_eq!(config.rpc_endpoint, None);
+        assert_eq!(config.rpc_cache, None);
+        assert_eq!(config.rpc_block, None);
+        assert!(config.contract_mocks.is_empty());
     }
 
     #[test]
@@ -52,5 +121,20 @@ mod config_tests {
         let config = MatchstickConfig::from("mocks/yamls/matchstick.yaml");
 
         assert_eq!(config.tests_path, "./specs".to_owned());
+        assert_eq!(config.fixtures_path, "./specs/fixtures".to_owned());
+        assert!(config.entity_cache);
+        assert!(!config.merge_store_set);
+        assert_eq!(config.unmocked_calls, UnmockedCallPolicy::Zero);
+        assert_eq!(config.unused_mocks, UnusedMockPolicy::Warn);
+        assert_eq!(
+            config.rpc_endpoint,
+            Some("http://127.0.0.1:8545".to_owned())
+        );
+        assert_eq!(config.rpc_cache, None);
+        assert_eq!(config.rpc_block, Some(15000000));
+        assert_eq!(
+            config.contract_mocks,
+            vec!["contract_mocks.yaml".to_owned()]
+        );
     }
 }
@@ -1,18 +1,22 @@
 use regex::Regex;
 use std::boxed::Box;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::{Arc, MutexGuard};
 
-use anyhow::Context;
+use anyhow::{anyhow, Context};
 use graph::{
     blockchain::Blockchain,
+    components::store::EntityCache,
     data::{
         graphql::ext::DirectiveFinder,
         store::{scalar::Bytes, Attribute, Value},
     },
     prelude::{
-        ethabi::{Address, ParamType, Token},
-        BigInt, Entity,
+        ethabi::{Address, Contract, ParamType, Token},
+        BigDecimal, BigInt, Entity,
     },
     runtime::{asc_get, asc_new, gas::GasCounter, try_asc_get, AscPtr, HostExportError},
     semver::Version,
@@ -33,15 +37,41 @@ use lazy_static::lazy_static;
 use serde_json::to_string_pretty;
 
 use crate::logging;
+use crate::writable_store::{EntityStore, MockWritableStore};
 use crate::SCHEMA_LOCATION;
 
-lazy_static! {
-    /// Special tokens...
-    pub(crate) static ref REVERTS_IDENTIFIER: Vec<Token> =
-        vec![Token::Bytes(vec![255, 255, 255, 255, 255, 255, 255])];
+mod abis;
+mod contract_mocks;
+mod derived;
+mod entity_cache;
+mod entity_schema;
+mod eth_rpc;
+mod fixtures;
+mod graphql;
+mod history;
+mod mock_calls;
+mod query;
+mod relation_graph;
+mod reverts;
+mod snapshots;
+mod unmocked_calls;
+mod unused_mocks;
+mod values;
+
+use derived::DerivedField;
+use entity_cache::merge_remove_null_fields;
+pub use eth_rpc::EthRpc;
+use history::CreatedDataSource;
+pub(crate) use history::{EntityChange, MockBlock};
+use mock_calls::{CallMatcher, MockId, MockedFunction, RecordedCall};
+use relation_graph::GraphFormat;
+pub(crate) use reverts::{CallOutcome, RevertData};
+pub use unmocked_calls::UnmockedCallPolicy;
+pub use unused_mocks::UnusedMockPolicy;
 
+lazy_static! {
     /// The global GraphQL Schema from `schema.graphql`.
-    static ref SCHEMA: schema::Document<'static, String> = {
+    pub(crate) static ref SCHEMA: schema::Document<'static, String> = {
         let mut s = "".to_owned();
         SCHEMA_LOCATION.with(|path| {
             s = std::fs::read_to_string(&*path.borrow()).unwrap_or_else(|err| {
@@ -67,29 +97,37 @@ lazy_static! {
 pub struct MatchstickInstanceContext<C: Blockchain> {
     /// Handle to WASM Instance Context.
     pub wasm_ctx: WasmInstanceContext<C>,
-    /// Store<EntityType, EntityTypeStore<EntityId, Entity<Field, Value>>>.
-    pub(crate) store: HashMap<String, HashMap<String, HashMap<String, Value>>>,
-    /// Function-Return map storing mocked Smart Contracts' functions' return values.
-    pub(crate) fn_ret_map: HashMap<String, Vec<Token>>,
+    /// Store<EntityType, EntityTypeStore<EntityId, Entity<Field, Value>>>, shared with the
+    /// `WritableStore` graph-node code uses.
+    pub(crate) store: Arc<MockWritableStore>,
+    /// Function-Return map storing how mocked Smart Contracts' function calls end.
+    pub(crate) fn_ret_map: HashMap<String, CallOutcome>,
+    /// Mocked functions matching their arguments with wildcards, ranges or prefixes,
+    /// used for calls that no exact mock in `fn_ret_map` matches.
+    pub(crate) call_matchers: Vec<CallMatcher>,
+    /// The contract, function and arguments of every call mocked by its exact arguments,
+    /// by the id of the call, to point out the mocks closest to a call that isn't mocked.
+    pub(crate) mocked_functions: HashMap<String, MockedFunction>,
+    /// The ABIs of the subgraph manifest loaded so far, by their name.
+    pub(crate) abis: HashMap<String, Contract>,
+    /// Outcomes of `mockFunctionOnce`, used once each and in order, before any other mock.
+    pub(crate) fn_ret_queue: HashMap<String, VecDeque<CallOutcome>>,
+    /// Outcomes of `mockFunctionAtBlock` by the block they are used from. Each is used
+    /// until the block of the next one, for calls made while handling later blocks.
+    pub(crate) fn_ret_by_block: HashMap<String, BTreeMap<u32, CallOutcome>>,
+    /// Every `ethereum.call` made in the current test, oldest first.
+    pub(crate) calls: Vec<RecordedCall>,
+    /// What `ethereum.call` does for calls that aren't mocked.
+    pub(crate) unmocked_call_policy: UnmockedCallPolicy,
+    /// The mocks created in the current test, `beforeEach` hooks included, oldest first.
+    pub(crate) test_mocks: Vec<MockId>,
+    /// The mocks contract calls were served from in the current test.
+    pub(crate) served_mocks: HashSet<MockId>,
     /// Registered tests metadata.
     pub meta_tests: Vec<(String, bool, u32)>,
-    /// Holding the derived field type and a tuple of the entity it points to
-    /// with a vector of all the field names and the corresponding derived field names.
-    /// The example below is taken from a schema.graphql file and will fill the map in the following way:
-    /// {"NameSignalTransaction": ("GraphAccount", [("nameSignalTransactions", "signer")])}
-    /// ```
-    /// type GraphAccount @entity {
-    ///     id: ID!
-    ///     nameSignalTransactions: [NameSignalTransaction!]! @derivedFrom(field: "signer")
-    /// }
-    /// type NameSignalTransaction @entity {
-    ///     id: ID!
-    ///     signer: GraphAccount!
-    /// }
-    /// ```
-    pub(crate) derived: HashMap<String, (String, Vec<(String, String)>)>,
-    /// Gives guarantee that all derived relations are in order when true
-    store_updated: bool,
+    /// Holding the entity type and all of its `@derivedFrom` fields.
+    /// Derived fields are not kept in the store, but resolved from it whenever an entity is read.
+    pub(crate) derived: HashMap<String, Vec<DerivedField>>,
     /// Holds the mocked return values of `dataSource.address()`, `dataSource.network()` and `dataSource.context()` in that order
     data_source_return_value: (
         Option<String>,
@@ -99,23 +137,67 @@ pub struct MatchstickInstanceContext<C: Blockchain> {
     /// Holds the mocked ipfs files in a HashMap, where key is the file hash, and the value is the
     /// path to the file that matchstick should read and parse
     pub(crate) ipfs: HashMap<String, String>,
+    /// The block and event set with `mockBlock`, which store changes are recorded with.
+    pub(crate) block: MockBlock,
+    /// Data sources created from templates, in the order they were created.
+    pub(crate) data_sources: Vec<CreatedDataSource>,
+    /// graph-node's entity cache over the store, holding the changes of the current block
+    /// when running with its semantics. They are written to the store when the block changes,
+    /// the cache is flushed, or anything but `store.get` reads the store.
+    pub(crate) entity_cache: RefCell<Option<EntityCache>>,
+    /// Whether `store.set` merges the fields into the stored entity like graph-node does,
+    /// instead of replacing the entity.
+    pub(crate) merge_store_set: bool,
 }
 
 /// Implementation of non-external functions.
 impl<C: Blockchain> MatchstickInstanceContext<C> {
-    pub fn new(wasm_ctx: WasmInstanceContext<C>) -> Self {
-        let mut context = MatchstickInstanceContext {
+    pub fn new(wasm_ctx: WasmInstanceContext<C>, store: Arc<MockWritableStore>) -> Self {
+        let entity_cache = crate::ENTITY_CACHE
+            .with(|enabled| *enabled.borrow())
+            .then(|| EntityCache::new(store.clone()));
+
+        MatchstickInstanceContext {
             wasm_ctx,
-            store: HashMap::new(),
+            store,
             fn_ret_map: HashMap::new(),
+            call_matchers: Vec::new(),
+            mocked_functions: HashMap::new(),
+            abis: HashMap::new(),
+            fn_ret_queue: HashMap::new(),
+            fn_ret_by_block: HashMap::new(),
+            calls: Vec::new(),
+            unmocked_call_policy: crate::UNMOCKED_CALLS.with(|policy| *policy.borrow()),
+            test_mocks: Vec::new(),
+            served_mocks: HashSet::new(),
             meta_tests: Vec::new(),
-            derived: HashMap::new(),
-            store_updated: true,
+            derived: derived::derive_schema(),
             data_source_return_value: (None, None, None),
             ipfs: HashMap::new(),
-        };
-        context.derive_schema();
-        context
+            block: MockBlock::default(),
+            data_sources: Vec::new(),
+            entity_cache: RefCell::new(entity_cache),
+            merge_store_set: crate::MERGE_STORE_SET.with(|enabled| *enabled.borrow()),
+        }
+    }
+
+    /// The entities in the store by type and id, after the pending changes of the
+    /// entity cache were written to it.
+    pub(crate) fn entities(&self) -> MutexGuard<'_, EntityStore> {
+        self.flush_cached_changes();
+        self.store.entities()
+    }
+
+    /// Resets what a test can change about how the host functions behave,
+    /// so that it doesn't carry over to the next test.
+    pub(crate) fn reset_test_state(&mut self) {
+        self.enable_entity_cache(crate::ENTITY_CACHE.with(|enabled| *enabled.borrow()));
+        self.merge_store_set = crate::MERGE_STORE_SET.with(|enabled| *enabled.borrow());
+        self.unmocked_call_policy = crate::UNMOCKED_CALLS.with(|policy| *policy.borrow());
+        // Contract calls and mocks are recorded per test, `beforeEach` hooks included.
+        self.calls.clear();
+        self.test_mocks.clear();
+        self.served_mocks.clear();
     }
 
     /// Constructs a unique ID for a given contract function.
@@ -131,6 +213,120 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         }
         unique_fn_string
     }
+
+    /// Keeps the parts of a mocked call, to describe it in errors, and returns its id.
+    fn register_mocked_function(
+        &mut self,
+        contract_address: Address,
+        fn_name: String,
+        fn_signature: String,
+        fn_args: Vec<Token>,
+    ) -> String {
+        let fn_id = MatchstickInstanceContext::<C>::fn_id(
+            &contract_address.to_string(),
+            &fn_name,
+            &fn_signature,
+            fn_args.clone(),
+        );
+        self.mocked_functions.insert(
+            fn_id.clone(),
+            MockedFunction {
+                contract_address,
+                fn_name,
+                fn_signature,
+                args: fn_args,
+            },
+        );
+        self.register_mock(MockId::Function(fn_id.clone()));
+        fn_id
+    }
+
+    /// Reads the call shared by the `mockFunction` host functions, checking the mocked arguments
+    /// against the function signature. Returns the id of the mocked call.
+    fn read_mocked_call(
+        &mut self,
+        contract_address_ptr: u32,
+        fn_name_ptr: AscPtr<AscString>,
+        fn_signature_ptr: AscPtr<AscString>,
+        fn_args_ptr: u32,
+    ) -> Result<String, HostExportError> {
+        let contract_address: Address = asc_get(
+            &self.wasm_ctx,
+            contract_address_ptr.into(),
+            &GasCounter::new(),
+        )?;
+        let fn_name: String = asc_get(&self.wasm_ctx, fn_name_ptr, &GasCounter::new())?;
+        let fn_signature: String = asc_get(&self.wasm_ctx, fn_signature_ptr, &GasCounter::new())?;
+        let fn_args: Vec<Token> = asc_get::<_, Array<AscPtr<AscEnum<EthereumValueKind>>>, _>(
+            &self.wasm_ctx,
+            fn_args_ptr.into(),
+            &GasCounter::new(),
+        )?;
+
+        let tmp_str = fn_signature.replace(&(fn_name.clone() + "("), "");
+        let components: Vec<&str> = tmp_str.split("):").collect();
+        let tmp_args_str = components[0];
+        let arg_types: Vec<String> = collect_types(tmp_args_str);
+
+        if arg_types.len() != fn_args.len() {
+            logging::critical!(
+                "{} expected {} arguments, but received {}",
+                fn_name,
+                arg_types.len(),
+                fn_args.len()
+            )
+        }
+
+        for (index, (arg_type, fn_arg)) in arg_types.iter().zip(fn_args.iter()).enumerate() {
+            let param_type = get_kind(arg_type.to_owned());
+
+            if !fn_arg.type_check(&param_type) {
+                logging::critical!(
+                    "createMockedFunction `{}` parameters mismatch at position {}:\nExpected: {:?}\nRecieved: {:?}\n",
+                    fn_name,
+                    index + 1,
+                    param_type,
+                    fn_arg
+                );
+            }
+        }
+
+        if self.signature_in_abis(&fn_signature) == Some(false) {
+            logging::warning!(
+                "createMockedFunction `{}` is not a function of any ABI in subgraph.yaml.",
+                fn_signature
+            );
+        }
+
+        Ok(self.register_mocked_function(contract_address, fn_name, fn_signature, fn_args))
+    }
+
+    /// Reads the arguments shared by the `mockFunction` host functions. Returns the id
+    /// of the mocked call and how it ends.
+    fn read_mocked_function(
+        &mut self,
+        contract_address_ptr: u32,
+        fn_name_ptr: AscPtr<AscString>,
+        fn_signature_ptr: AscPtr<AscString>,
+        fn_args_ptr: u32,
+        return_value_ptr: u32,
+        reverts_ptr: AscPtr<bool>,
+    ) -> Result<(String, CallOutcome), HostExportError> {
+        let fn_id = self.read_mocked_call(
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+        )?;
+        let return_value: Vec<Token> = asc_get::<_, Array<AscPtr<AscEnum<EthereumValueKind>>>, _>(
+            &self.wasm_ctx,
+            return_value_ptr.into(),
+            &GasCounter::new(),
+        )?;
+        let reverts = bool::from(EnumPayload(reverts_ptr.to_payload()));
+
+        Ok((fn_id, CallOutcome::mocked(return_value, reverts)))
+    }
 }
 
 /// Implementation of external functions (used in AssemblyScript sources).
@@ -156,15 +352,50 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
     pub fn log_store(&mut self, _gas: &GasCounter) -> Result<(), HostExportError> {
         logging::debug!(
             "{}",
-            to_string_pretty(&self.store).unwrap_or_else(|err| logging::critical!(err)),
+            to_string_pretty(&self.store_with_derived_fields())
+                .unwrap_or_else(|err| logging::critical!(err)),
         );
         Ok(())
     }
 
+    /// function storeGraph(format: string): string
+    pub fn store_graph(
+        &mut self,
+        _gas: &GasCounter,
+        format_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscString>, HostExportError> {
+        let format: String = asc_get(&self.wasm_ctx, format_ptr, &GasCounter::new())?;
+        let format = GraphFormat::parse(&format)
+            .unwrap_or_else(|err| logging::critical!("(storeGraph) {}", err));
+
+        let result = AscPtr::alloc_obj(
+            asc_string_from_str(&self.relation_graph(format)),
+            &mut self.wasm_ctx,
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create pointer.");
+        Ok(result)
+    }
+
+    /// function logStoreGraph(format: string): void
+    pub fn log_store_graph(
+        &mut self,
+        _gas: &GasCounter,
+        format_ptr: AscPtr<AscString>,
+    ) -> Result<(), HostExportError> {
+        let format: String = asc_get(&self.wasm_ctx, format_ptr, &GasCounter::new())?;
+        let format = GraphFormat::parse(&format)
+            .unwrap_or_else(|err| logging::critical!("(logStoreGraph) {}", err));
+
+        logging::debug!("{}", self.relation_graph(format));
+        Ok(())
+    }
+
     /// function clearStore(): void
     pub fn clear_store(&mut self, _gas: &GasCounter) -> Result<(), HostExportError> {
         self.store.clear();
-        self.store_updated = true;
+        self.data_sources.clear();
+        self.discard_cached_changes();
         Ok(())
     }
 
@@ -194,54 +425,172 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         field_name_ptr: AscPtr<AscString>,
         expected_val_ptr: AscPtr<AscString>,
     ) -> Result<bool, HostExportError> {
-        self.update_derived_relations_in_store();
         let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
         let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
         let field_name: String = asc_get(&self.wasm_ctx, field_name_ptr, &GasCounter::new())?;
         let expected_val: String = asc_get(&self.wasm_ctx, expected_val_ptr, &GasCounter::new())?;
 
-        if !self.store.contains_key(&entity_type) {
-            logging::error!(
-                "(assert.fieldEquals) No entities with type '{}' found.",
-                &entity_type
-            );
+        Ok(self.field_equals(&entity_type, &id, &field_name, &expected_val, None))
+    }
 
-            return Ok(false);
-        }
+    /// function _assert.fieldEqualsWithTolerance(
+    ///     entityType: string, id: string,
+    ///     fieldName: string, expectedVal: string, tolerance: string,
+    /// ): bool
+    pub fn assert_field_equals_with_tolerance(
+        &mut self,
+        _gas: &GasCounter,
+        entity_type_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+        field_name_ptr: AscPtr<AscString>,
+        expected_val_ptr: AscPtr<AscString>,
+        tolerance_ptr: AscPtr<AscString>,
+    ) -> Result<bool, HostExportError> {
+        let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
+        let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
+        let field_name: String = asc_get(&self.wasm_ctx, field_name_ptr, &GasCounter::new())?;
+        let expected_val: String = asc_get(&self.wasm_ctx, expected_val_ptr, &GasCounter::new())?;
+        let tolerance: String = asc_get(&self.wasm_ctx, tolerance_ptr, &GasCounter::new())?;
 
-        let entities = self.store.get(&entity_type).unwrap();
-        if !entities.contains_key(&id) {
-            logging::error!(
-                "(assert.fieldEquals) No entity with type '{}' and id '{}' found.",
-                &entity_type,
-                &id
-            );
+        let tolerance = BigDecimal::from_str(&tolerance).unwrap_or_else(|err| {
+            logging::critical!(
+                "(assert.fieldEqualsWithTolerance) `{}` is not a valid tolerance: {}",
+                tolerance,
+                err
+            )
+        });
 
-            return Ok(false);
-        }
+        Ok(self.field_equals(
+            &entity_type,
+            &id,
+            &field_name,
+            &expected_val,
+            Some(&tolerance),
+        ))
+    }
+
+    /// Compares a field of a stored entity with the expected value, see `values::field_matches`.
+    fn field_equals(
+        &self,
+        entity_type: &str,
+        id: &str,
+        field_name: &str,
+        expected_val: &str,
+        tolerance: Option<&BigDecimal>,
+    ) -> bool {
+        let stored = self
+            .entities()
+            .get(entity_type)
+            .map(|entities| entities.get(id).cloned());
+        let entity = match stored {
+            Some(Some(entity)) => self.with_derived_fields(entity_type, id, &entity),
+            Some(None) => {
+                logging::error!(
+                    "(assert.fieldEquals) No entity with type '{}' and id '{}' found.",
+                    entity_type,
+                    id
+                );
+
+                return false;
+            }
+            None => {
+                logging::error!(
+                    "(assert.fieldEquals) No entities with type '{}' found.",
+                    entity_type
+                );
 
-        let entity = entities.get(&id).unwrap();
-        if !entity.contains_key(&field_name) {
+                return false;
+            }
+        };
+        if !entity.contains_key(field_name) {
             logging::error!(
                 "(assert.fieldEquals) No field named '{}' on entity with type '{}' and id '{}' found.",
-                &field_name,
-                &entity_type,
-                &id
+                field_name,
+                entity_type,
+                id
             );
 
-            return Ok(false);
+            return false;
         }
 
-        let val = entity.get(&field_name).unwrap();
-        if val.to_string() != expected_val {
+        let val = entity.get(field_name).unwrap();
+        if !values::field_matches(entity_type, field_name, val, expected_val, tolerance) {
+            match tolerance {
+                Some(tolerance) => logging::error!(
+                    "(assert.fieldEquals) Expected field '{}' to equal '{}' within '{}', but was '{}' instead.",
+                    field_name,
+                    expected_val,
+                    tolerance,
+                    val
+                ),
+                None => logging::error!(
+                    "(assert.fieldEquals) Expected field '{}' to equal '{}', but was '{}' instead.",
+                    field_name,
+                    expected_val,
+                    val
+                ),
+            }
+            return false;
+        };
+
+        true
+    }
+
+    /// function _assert.entityEquals(entityType: string, id: string, expected: Entity): bool
+    pub fn assert_entity_equals(
+        &mut self,
+        _gas: &GasCounter,
+        entity_type_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+        expected_ptr: AscPtr<AscEntity>,
+    ) -> Result<bool, HostExportError> {
+        let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
+        let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
+        let expected: HashMap<String, Value> =
+            try_asc_get(&self.wasm_ctx, expected_ptr, &GasCounter::new())?;
+
+        let stored = self
+            .entities()
+            .get(&entity_type)
+            .and_then(|e| e.get(&id))
+            .cloned();
+        let actual = match stored {
+            Some(entity) => self.with_derived_fields(&entity_type, &id, &entity),
+            None => {
+                logging::error!(
+                    "(assert.entityEquals) No entity with type '{}' and id '{}' found.",
+                    &entity_type,
+                    &id
+                );
+                return Ok(false);
+            }
+        };
+
+        // Derived fields are only compared when the expected entity has them.
+        let derived_fields = self.derived.get(&entity_type);
+        let actual: HashMap<String, Value> = actual
+            .into_iter()
+            .filter(|(field, _)| {
+                expected.contains_key(field)
+                    || !derived_fields.map_or(false, |fields| {
+                        fields.iter().any(|f| &f.field_name == field)
+                    })
+            })
+            .collect();
+
+        let diff = values::diff_entities(&expected, &actual);
+        if !diff.is_empty() {
             logging::error!(
-                "(assert.fieldEquals) Expected field '{}' to equal '{}', but was '{}' instead.",
-                &field_name,
-                &expected_val,
-                val
+                "(assert.entityEquals) Entity with type '{}' and id '{}' differs from the expected entity:\n  {}",
+                &entity_type,
+                &id,
+                diff.join("\n  ")
             );
             return Ok(false);
-        };
+        }
 
         Ok(true)
     }
@@ -253,7 +602,6 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         expected_ptr: u32,
         actual_ptr: u32,
     ) -> Result<bool, HostExportError> {
-        self.update_derived_relations_in_store();
         let expected: Token = asc_get::<_, AscEnum<EthereumValueKind>, _>(
             &self.wasm_ctx,
             expected_ptr.into(),
@@ -287,13 +635,15 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         entity_type_ptr: AscPtr<AscString>,
         id_ptr: AscPtr<AscString>,
     ) -> Result<bool, HostExportError> {
-        self.update_derived_relations_in_store();
         let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
         let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
 
-        if self.store.contains_key(&entity_type)
-            && self.store.get(&entity_type).unwrap().contains_key(&id)
-        {
+        let found = self
+            .entities()
+            .get(&entity_type)
+            .map_or(false, |entities| entities.contains_key(&id));
+        if found {
             logging::error!(
                 "(assert.notInStore) Value for entity type: '{}' and id: '{}' was found in store.",
                 entity_type,
@@ -305,6 +655,17 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         Ok(true)
     }
 
+    /// function _assert.storeMatchesSnapshot(name: string): bool
+    pub fn assert_store_matches_snapshot(
+        &mut self,
+        _gas: &GasCounter,
+        name_ptr: AscPtr<AscString>,
+    ) -> Result<bool, HostExportError> {
+        let name: String = asc_get(&self.wasm_ctx, name_ptr, &GasCounter::new())?;
+
+        Ok(self.matches_snapshot(&name))
+    }
+
     /// function store.get(entityType: string, id: string): Entity
     pub fn mock_store_get(
         &mut self,
@@ -312,15 +673,12 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         entity_type_ptr: AscPtr<AscString>,
         id_ptr: AscPtr<AscString>,
     ) -> Result<AscPtr<AscEntity>, HostExportError> {
-        self.update_derived_relations_in_store();
         let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
         let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
 
-        if self.store.contains_key(&entity_type)
-            && self.store.get(&entity_type).unwrap().contains_key(&id)
-        {
-            let entities = self.store.get(&entity_type).unwrap();
-            let entity = entities.get(&id).unwrap().clone();
+        if let Some(entity) = self.cached_entity(&entity_type, &id) {
+            let entity = self.with_derived_fields(&entity_type, &id, &entity);
             let entity = Entity::from(entity);
 
             let res = asc_new(&mut self.wasm_ctx, &entity.sorted(), &GasCounter::new())?;
@@ -340,29 +698,17 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
     ) -> Result<(), HostExportError> {
         let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
         let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
-        let data: HashMap<String, Value> =
+        let id = entity_schema::id_key(&entity_type, &id);
+        let mut data: HashMap<String, Value> =
             try_asc_get(&self.wasm_ctx, data_ptr, &GasCounter::new())?;
 
-        let required_fields = SCHEMA
-            .definitions
-            .iter()
-            .find_map(|def| {
-                if let schema::Definition::TypeDefinition(schema::TypeDefinition::Object(o)) = def {
-                    if o.name == entity_type {
-                        Some(o)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .unwrap_or_else(|| {
+        let entity_definition =
+            entity_schema::entity_definition(&entity_type).unwrap_or_else(|| {
                 logging::critical!("Something went wrong! Could not find the entity defined in the GraphQL schema.")
-            })
+            });
+        let required_fields = entity_definition
             .fields
             .iter()
-            .clone()
             .filter(|&f| matches!(f.field_type, schema::Type::NonNullType(..)) && !f.is_derived());
 
         for f in required_fields {
@@ -381,303 +727,41 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
             }
         }
 
-        if self.derived.contains_key(&entity_type) {
-            let linking_fields = self
-                .derived
-                .get(&entity_type)
-                .unwrap_or_else(|| {
-                    logging::critical!("Couldn't find value for key {} in derived map", entity_type)
-                })
-                .1
-                .clone();
-            let original_entity_type = self.derived.get(&entity_type).unwrap().0.clone();
-            for linking_field in linking_fields {
-                if data.contains_key(&linking_field.1) {
-                    let derived_field_value = data
-                        .get(&linking_field.1)
-                        .unwrap_or_else(|| {
-                            logging::critical!(
-                                "Couldn't find value for {} in submitted data",
-                                linking_field.1
-                            )
-                        })
-                        .clone();
-                    if matches!(derived_field_value, Value::List(_)) {
-                        for derived_field_value in derived_field_value.as_list().unwrap().clone() {
-                            self.insert_derived_field_in_store(
-                                derived_field_value,
-                                original_entity_type.clone(),
-                                linking_field.clone(),
-                                id.clone(),
-                            );
-                        }
-                    } else {
-                        self.insert_derived_field_in_store(
-                            derived_field_value,
-                            original_entity_type.clone(),
-                            linking_field.clone(),
-                            id.clone(),
-                        );
-                    }
-                }
-            }
-        }
-
-        let mut entity_type_store = if self.store.contains_key(&entity_type) {
-            self.store.get(&entity_type).unwrap().clone()
-        } else {
-            HashMap::new()
-        };
-
-        entity_type_store.insert(id, data);
-        self.store.insert(entity_type.clone(), entity_type_store);
-        self.store_updated = false;
-        Ok(())
-    }
-
-    /// This function checks whether all the necessary data is present in the store to avoid linking
-    /// entities to other non existent entities which may cause serious collision problems later
-    fn insert_derived_field_in_store(
-        &mut self,
-        derived_field_value: Value,
-        original_entity: String,
-        linking_field: (String, String),
-        id: String,
-    ) {
-        if derived_field_value.is_string() {
-            let derived_field_string_value = derived_field_value.as_string().unwrap();
-            if self.store.contains_key(&original_entity) {
-                let mut inner_store = self
-                    .store
-                    .get(&original_entity)
-                    .unwrap_or_else(|| {
-                        logging::critical!("Couldn't find value for {} in store", original_entity)
-                    })
-                    .clone();
-                if inner_store.contains_key(&derived_field_string_value) {
-                    let mut innermost_store = inner_store
-                        .get(&derived_field_string_value)
-                        .unwrap_or_else(|| {
-                            logging::critical!(
-                                "Couldn't find value for {} in inner store",
-                                derived_field_string_value
-                            )
-                        })
-                        .clone();
-                    if innermost_store.contains_key(&linking_field.0) {
-                        let innermost_value = innermost_store
-                            .get(&linking_field.0)
-                            .unwrap_or_else(|| {
-                                logging::critical!(
-                                    "Couldn't find value for {} in innermost store",
-                                    linking_field.0
-                                )
-                            })
-                            .clone();
-                        if !innermost_value
-                            .clone()
-                            .as_list()
-                            .unwrap()
-                            .contains(&Value::from(id.clone()))
-                        {
-                            let mut innermost_value_list = innermost_value.as_list().unwrap();
-                            innermost_value_list.push(Value::from(id));
-                            innermost_store
-                                .insert(linking_field.0, Value::List(innermost_value_list));
-                        }
-                    } else {
-                        innermost_store.insert(linking_field.0, Value::List(vec![Value::from(id)]));
-                    }
-                    inner_store.insert(derived_field_string_value, innermost_store);
-                }
-                self.store.insert(original_entity, inner_store);
+        // Derived fields are resolved when the entity is read, so they are never stored.
+        for derived_field in self.derived.get(&entity_type).into_iter().flatten() {
+            if data.remove(&derived_field.field_name).is_some() {
+                logging::warning!(
+                    "The field '{}' for an entity of type '{}' is derived and can not be set.",
+                    derived_field.field_name,
+                    entity_type,
+                );
             }
         }
-    }
 
-    /// Checks for and removes faulty store relations.
-    /// This function is called on every assert/store.get to make sure assertions and entity loading is done with an updated store
-    fn update_derived_relations_in_store(&mut self) {
-        if !self.store_updated {
-            for entity in self.store.clone() {
-                for inner_entity in entity.1 {
-                    let entity_type = entity.0.clone();
-                    let id = inner_entity.0;
-                    let data = inner_entity.1;
-                    if self.derived.contains_key(&entity_type) {
-                        let linking_fields = self
-                            .derived
-                            .get(&entity_type)
-                            .unwrap_or_else(|| {
-                                logging::critical!(
-                                    "Couldn't find value for key {} in derived map",
-                                    entity_type
-                                )
-                            })
-                            .1
-                            .clone();
-                        let original_entity_type =
-                            self.derived.get(&entity_type).unwrap().0.clone();
-                        if self.store.contains_key(&original_entity_type) {
-                            let inner_store =
-                                self.store.get(&original_entity_type).unwrap().clone();
-                            let mut entity_deleted = true;
-                            for linking_field in &linking_fields {
-                                let relation_id = data.get(&linking_field.1).unwrap();
-                                if relation_id.is_string()
-                                    && inner_store.contains_key(relation_id.as_str().unwrap())
-                                {
-                                    entity_deleted = false;
-                                    let original_entity_data =
-                                        inner_store.get(relation_id.as_str().unwrap()).unwrap();
-                                    for field in original_entity_data {
-                                        if &linking_field.0 == field.0
-                                            && matches!(field.1, Value::List(_))
-                                        {
-                                            let value_list =
-                                                field.1.clone().as_list().unwrap().clone();
-                                            if !value_list.contains(&Value::String(id.clone()))
-                                                && data.contains_key(&linking_field.1)
-                                            {
-                                                self.handle_different_value_types(
-                                                    data.clone(),
-                                                    linking_field,
-                                                    relation_id,
-                                                    field,
-                                                    id.clone(),
-                                                    original_entity_type.clone(),
-                                                    entity_deleted,
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-
-                            // Removes the entity with no relations from every list it may be in
-                            if entity_deleted {
-                                for linking_field in &linking_fields {
-                                    let relation_id = data.get(&linking_field.1).unwrap();
-                                    if relation_id.is_string() {
-                                        for original_entity_id_and_data in &inner_store {
-                                            let innermost_store = inner_store
-                                                .get(original_entity_id_and_data.0)
-                                                .unwrap()
-                                                .clone();
-                                            for field in &innermost_store {
-                                                if &linking_field.0 == field.0
-                                                    && matches!(field.1, Value::List(_))
-                                                {
-                                                    let value_list =
-                                                        field.1.clone().as_list().unwrap().clone();
-                                                    if value_list
-                                                        .contains(&Value::String(id.clone()))
-                                                        && data.contains_key(&linking_field.1)
-                                                    {
-                                                        self.handle_different_value_types(
-                                                            data.clone(),
-                                                            linking_field,
-                                                            relation_id,
-                                                            field,
-                                                            id.clone(),
-                                                            original_entity_type.clone(),
-                                                            entity_deleted,
-                                                        );
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+        if self.entity_cache.borrow().is_some() {
+            // A remove before the set makes graph-node replace the entity instead of merging.
+            if !self.merge_store_set {
+                self.cache_remove(&entity_type, &id);
             }
-            self.store_updated = true;
+            self.cache_set(&entity_type, &id, data);
+            return Ok(());
         }
-    }
 
-    #[allow(clippy::too_many_arguments)]
-    fn handle_different_value_types(
-        &mut self,
-        data: HashMap<String, Value>,
-        linking_field: &(String, String),
-        relation_id: &Value,
-        field: (&String, &Value),
-        id: String,
-        original_entity_type: String,
-        entity_deleted: bool,
-    ) {
-        if data.get(&linking_field.1).unwrap().is_string() {
-            self.remove_dead_relations(
-                data.get(&linking_field.1).unwrap().to_owned(),
-                relation_id.as_str().unwrap(),
-                field,
-                Value::String(id),
-                original_entity_type,
-                entity_deleted,
-            );
-        } else if matches!(data.get(&linking_field.1).unwrap(), Value::List(_)) {
-            let linking_field_values = data
-                .get(&linking_field.1)
-                .unwrap()
-                .clone()
-                .as_list()
-                .unwrap();
-            for linking_field_value in linking_field_values {
-                self.remove_dead_relations(
-                    linking_field_value,
-                    relation_id.as_str().unwrap(),
-                    field,
-                    Value::String(id.clone()),
-                    original_entity_type.clone(),
-                    entity_deleted,
-                );
-            }
+        // Like graph-node, only the passed fields are updated and fields set to null are removed.
+        if self.merge_store_set {
+            let mut merged = self
+                .entities()
+                .get(&entity_type)
+                .and_then(|entities| entities.get(&id))
+                .cloned()
+                .unwrap_or_default();
+            merge_remove_null_fields(&mut merged, data);
+            data = merged;
         }
-    }
-
-    fn remove_dead_relations(
-        &mut self,
-        current_value: Value,
-        entity: &str,
-        field: (&String, &Value),
-        value: Value,
-        original_entity: String,
-        entity_deleted: bool,
-    ) {
-        if current_value.is_string()
-            && (current_value.as_str().unwrap() != entity && !entity_deleted)
-            || (current_value.as_str().unwrap() == entity && entity_deleted)
-        {
-            let mut inner_store = self.store.get(&original_entity).unwrap().clone();
-            if !entity_deleted {
-                let mut innermost_store = inner_store.get(entity).unwrap().clone();
-                let mut value_list = field.1.clone().as_list().unwrap();
 
-                if value_list.contains(&value) {
-                    value_list.remove(value_list.iter().position(|x| *x == value).unwrap());
-                    innermost_store.insert(field.0.to_owned(), Value::List(value_list));
-                    inner_store.insert(entity.to_owned(), innermost_store);
-
-                    self.store.insert(original_entity, inner_store);
-                }
-            } else {
-                for mut entity in inner_store.clone() {
-                    let mut value_list = field.1.clone().as_list().unwrap();
-
-                    if value_list.contains(&value) {
-                        value_list.remove(value_list.iter().position(|x| *x == value).unwrap());
-                        entity.1.insert(field.0.to_owned(), Value::List(value_list));
-                        inner_store.insert(entity.0, entity.1);
-
-                        self.store
-                            .insert(original_entity.clone(), inner_store.clone());
-                    }
-                }
-            }
-        }
+        self.store
+            .write_entity(&self.block, &entity_type, &id, Some(data));
+        Ok(())
     }
 
     /// function store.remove(entityType: string, id: string): void
@@ -689,18 +773,25 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
     ) -> Result<(), HostExportError> {
         let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
         let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
 
-        if self.store.contains_key(&entity_type)
-            && self.store.get(&entity_type).unwrap().contains_key(&id)
-        {
-            if self.derived.contains_key(&entity_type) {
-                self.cascade_remove(entity_type.clone(), id.clone());
+        if self.entity_cache.borrow().is_some() {
+            if self.cached_entity(&entity_type, &id).is_some() {
+                self.cache_remove(&entity_type, &id);
+            } else {
+                logging::error!(
+                    "(store.remove) Entity with type '{}' and id '{}' does not exist.",
+                    &entity_type,
+                    &id
+                );
             }
-            let mut entity_type_store = self.store.get(&entity_type).unwrap().clone();
-            entity_type_store.remove(&id);
-
-            self.store.insert(entity_type, entity_type_store);
-            self.store_updated = false;
+        } else if self
+            .entities()
+            .get(&entity_type)
+            .map_or(false, |entities| entities.contains_key(&id))
+        {
+            self.store
+                .write_entity(&self.block, &entity_type, &id, None);
         } else {
             logging::error!(
                 "(store.remove) Entity with type '{}' and id '{}' does not exist.",
@@ -712,59 +803,6 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         Ok(())
     }
 
-    /// Removes the deleted entity from the other end of any relations it may have
-    fn cascade_remove(&mut self, entity_type: String, id: String) {
-        let store = self.store.clone();
-        let deleted_entity_data = store.get(&entity_type).unwrap().get(&id).unwrap();
-        let linking_fields = self
-            .derived
-            .get(&entity_type)
-            .unwrap_or_else(|| {
-                logging::critical!("Couldn't find value for key {} in derived map", entity_type)
-            })
-            .1
-            .clone();
-
-        let original_entity_type = self.derived.get(&entity_type).unwrap().0.clone();
-        if self.store.contains_key(&original_entity_type) {
-            let mut original_entity = store.get(&original_entity_type).unwrap().clone();
-            for linking_field in linking_fields {
-                if deleted_entity_data.contains_key(&linking_field.1) {
-                    let relation_id = deleted_entity_data.get(&linking_field.1).unwrap();
-                    if relation_id.is_string()
-                        && original_entity.contains_key(relation_id.as_str().unwrap())
-                    {
-                        let mut inner_store = original_entity
-                            .get(relation_id.as_str().unwrap())
-                            .unwrap()
-                            .clone();
-                        if inner_store.contains_key(&linking_field.0) {
-                            let mut value_list = inner_store
-                                .get(&linking_field.0)
-                                .unwrap()
-                                .clone()
-                                .as_list()
-                                .unwrap();
-                            if value_list.contains(&Value::String(id.clone())) {
-                                value_list.remove(
-                                    value_list
-                                        .iter()
-                                        .position(|x| *x == Value::String(id.clone()))
-                                        .unwrap(),
-                                );
-                                inner_store.insert(linking_field.0, Value::List(value_list));
-                                original_entity
-                                    .insert(relation_id.clone().as_string().unwrap(), inner_store);
-                                self.store
-                                    .insert(original_entity_type.clone(), original_entity.clone());
-                            }
-                        }
-                    }
-                }
-            }
-        }
-    }
-
     /// function ethereum.call(call: SmartContractCall): Array<Value> | null
     pub fn ethereum_call(
         &mut self,
@@ -777,38 +815,97 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
             &GasCounter::new(),
         )?;
 
+        let fn_signature = call
+            .function_signature
+            .unwrap_or_else(|| logging::critical!("Could not get function signature."));
         let fn_id = MatchstickInstanceContext::<C>::fn_id(
             &call.contract_address.to_string(),
             &call.function_name,
-            &call
-                .function_signature
-                .unwrap_or_else(|| logging::critical!("Could not get function signature.")),
-            call.function_args,
+            &fn_signature,
+            call.function_args.clone(),
         );
 
-        let return_val;
-        if self.fn_ret_map.contains_key(&fn_id) {
-            if *self.fn_ret_map.get(&fn_id).unwrap() == REVERTS_IDENTIFIER.clone() {
-                return Ok(AscPtr::null());
+        let outcome = self
+            .mocked_outcome(
+                &fn_id,
+                &call.contract_address,
+                &call.function_name,
+                &fn_signature,
+                &call.function_args,
+            )
+            .or_else(|| {
+                self.forwarded_call(
+                    &call.contract_address,
+                    &call.function_name,
+                    &fn_signature,
+                    &call.function_args,
+                )
+                .map(|outcome| {
+                    outcome.unwrap_or_else(|err| {
+                        CallOutcome::Failed(format!("forwarding the call failed: {}", err))
+                    })
+                })
+            });
+        let mocked = outcome.is_some();
+        let outcome = match (outcome, self.unmocked_call_policy) {
+            (Some(outcome), _) => outcome,
+            (None, UnmockedCallPolicy::Fail) => {
+                return Err(HostExportError::Deterministic(anyhow!(self
+                    .unmocked_call_error(
+                        &call.contract_address,
+                        &call.function_name,
+                        &fn_signature,
+                        &call.function_args,
+                    ))))
             }
+            (None, UnmockedCallPolicy::Revert) => CallOutcome::Reverted(None),
+            (None, UnmockedCallPolicy::Zero) => CallOutcome::Returned(
+                unmocked_calls::return_types(&fn_signature)
+                    .iter()
+                    .map(unmocked_calls::zero_token)
+                    .collect(),
+            ),
+        };
 
-            return_val = asc_new(
-                &mut self.wasm_ctx,
-                self.fn_ret_map
-                    .get(&fn_id)
-                    .unwrap_or_else(|| logging::critical!("Could not get value from function map."))
-                    .as_slice(),
-                &GasCounter::new(),
-            )?;
+        self.calls.push(RecordedCall {
+            contract_address: call.contract_address,
+            fn_name: call.function_name,
+            fn_signature: fn_signature.clone(),
+            args: call.function_args,
+            result: outcome.clone(),
+            mocked,
+            verified: false,
+        });
 
-            Ok(return_val)
-        } else {
-            logging::critical!(
-                "Key: '{}' not found in map. Please mock the function before calling it.",
-                &fn_id,
-            );
-        }
-    }
+        match outcome {
+            CallOutcome::Returned(return_value) => {
+                let return_val = asc_new(
+                    &mut self.wasm_ctx,
+                    return_value.as_slice(),
+                    &GasCounter::new(),
+                )?;
+                Ok(return_val)
+            }
+            // A null result is what the `try_` calls of the generated bindings take as a revert.
+            CallOutcome::Reverted(revert) => {
+                if let Some(revert) = revert {
+                    logging::info!(
+                        "`{}` of {:?} reverted with {}",
+                        fn_signature,
+                        call.contract_address,
+                        revert
+                    );
+                }
+                Ok(AscPtr::null())
+            }
+            CallOutcome::Failed(message) => Err(HostExportError::Deterministic(anyhow!(
+                "The call of `{}` to {:?} failed: {}",
+                fn_signature,
+                call.contract_address,
+                message
+            ))),
+        }
+    }
 
     /// function mockFunction(
     ///     contractAddress: Address, fnName: string, fnSignature: string,
@@ -825,12 +922,194 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         return_value_ptr: u32,
         reverts_ptr: AscPtr<bool>,
     ) -> Result<(), HostExportError> {
+        let (fn_id, outcome) = self.read_mocked_function(
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+            return_value_ptr,
+            reverts_ptr,
+        )?;
+        self.fn_ret_map.insert(fn_id, outcome);
+
+        Ok(())
+    }
+
+    /// function mockFunctionRevertsWithReason(
+    ///     contractAddress: Address, fnName: string, fnSignature: string,
+    ///     fnArgs: ethereum.Value[], reason: string,
+    /// ): void
+    pub fn mock_function_reverts_with_reason(
+        &mut self,
+        _gas: &GasCounter,
+        contract_address_ptr: u32,
+        fn_name_ptr: AscPtr<AscString>,
+        fn_signature_ptr: AscPtr<AscString>,
+        fn_args_ptr: u32,
+        reason_ptr: AscPtr<AscString>,
+    ) -> Result<(), HostExportError> {
+        let fn_id = self.read_mocked_call(
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+        )?;
+        let reason: String = asc_get(&self.wasm_ctx, reason_ptr, &GasCounter::new())?;
+
+        self.fn_ret_map.insert(
+            fn_id,
+            CallOutcome::Reverted(Some(RevertData::Reason(reason))),
+        );
+        Ok(())
+    }
+
+    /// function mockFunctionRevertsWithError(
+    ///     contractAddress: Address, fnName: string, fnSignature: string,
+    ///     fnArgs: ethereum.Value[], errorSignature: string, errorArgs: ethereum.Value[],
+    /// ): void
+    #[allow(clippy::too_many_arguments)]
+    pub fn mock_function_reverts_with_error(
+        &mut self,
+        _gas: &GasCounter,
+        contract_address_ptr: u32,
+        fn_name_ptr: AscPtr<AscString>,
+        fn_signature_ptr: AscPtr<AscString>,
+        fn_args_ptr: u32,
+        error_signature_ptr: AscPtr<AscString>,
+        error_args_ptr: u32,
+    ) -> Result<(), HostExportError> {
+        let fn_id = self.read_mocked_call(
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+        )?;
+        let error_signature: String =
+            asc_get(&self.wasm_ctx, error_signature_ptr, &GasCounter::new())?;
+        let error_args: Vec<Token> = asc_get::<_, Array<AscPtr<AscEnum<EthereumValueKind>>>, _>(
+            &self.wasm_ctx,
+            error_args_ptr.into(),
+            &GasCounter::new(),
+        )?;
+
+        let revert = reverts::custom_error(&error_signature, &error_args)
+            .unwrap_or_else(|err| logging::critical!("(mockFunctionRevertsWithError) {}", err));
+        self.fn_ret_map
+            .insert(fn_id, CallOutcome::Reverted(Some(revert)));
+        Ok(())
+    }
+
+    /// function mockFunctionFails(
+    ///     contractAddress: Address, fnName: string, fnSignature: string,
+    ///     fnArgs: ethereum.Value[], message: string,
+    /// ): void
+    pub fn mock_function_fails(
+        &mut self,
+        _gas: &GasCounter,
+        contract_address_ptr: u32,
+        fn_name_ptr: AscPtr<AscString>,
+        fn_signature_ptr: AscPtr<AscString>,
+        fn_args_ptr: u32,
+        message_ptr: AscPtr<AscString>,
+    ) -> Result<(), HostExportError> {
+        let fn_id = self.read_mocked_call(
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+        )?;
+        let message: String = asc_get(&self.wasm_ctx, message_ptr, &GasCounter::new())?;
+
+        self.fn_ret_map.insert(fn_id, CallOutcome::Failed(message));
+        Ok(())
+    }
+
+    /// function mockFunctionOnce(
+    ///     contractAddress: Address, fnName: string, fnSignature: string,
+    ///     fnArgs: ethereum.Value[], returnValue: ethereum.Value[], reverts: bool,
+    /// ): void
+    #[allow(clippy::too_many_arguments)]
+    pub fn mock_function_once(
+        &mut self,
+        _gas: &GasCounter,
+        contract_address_ptr: u32,
+        fn_name_ptr: AscPtr<AscString>,
+        fn_signature_ptr: AscPtr<AscString>,
+        fn_args_ptr: u32,
+        return_value_ptr: u32,
+        reverts_ptr: AscPtr<bool>,
+    ) -> Result<(), HostExportError> {
+        let (fn_id, outcome) = self.read_mocked_function(
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+            return_value_ptr,
+            reverts_ptr,
+        )?;
+        self.fn_ret_queue
+            .entry(fn_id)
+            .or_default()
+            .push_back(outcome);
+
+        Ok(())
+    }
+
+    /// function mockFunctionAtBlock(
+    ///     contractAddress: Address, fnName: string, fnSignature: string,
+    ///     fnArgs: ethereum.Value[], returnValue: ethereum.Value[], reverts: bool, blockNumber: i32,
+    /// ): void
+    #[allow(clippy::too_many_arguments)]
+    pub fn mock_function_at_block(
+        &mut self,
+        _gas: &GasCounter,
+        contract_address_ptr: u32,
+        fn_name_ptr: AscPtr<AscString>,
+        fn_signature_ptr: AscPtr<AscString>,
+        fn_args_ptr: u32,
+        return_value_ptr: u32,
+        reverts_ptr: AscPtr<bool>,
+        block_number: u32,
+    ) -> Result<(), HostExportError> {
+        let (fn_id, outcome) = self.read_mocked_function(
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+            return_value_ptr,
+            reverts_ptr,
+        )?;
+        let block_number =
+            values::non_negative("mockFunctionAtBlock", "Block number", block_number);
+        self.fn_ret_by_block
+            .entry(fn_id)
+            .or_default()
+            .insert(block_number, outcome);
+
+        Ok(())
+    }
+
+    /// function mockContractFunction(
+    ///     contractName: string, contractAddress: Address, fnSignature: string,
+    ///     fnArgs: ethereum.Value[], returnValue: ethereum.Value[], reverts: bool,
+    /// ): void
+    #[allow(clippy::too_many_arguments)]
+    pub fn mock_contract_function(
+        &mut self,
+        _gas: &GasCounter,
+        contract_name_ptr: AscPtr<AscString>,
+        contract_address_ptr: u32,
+        fn_signature_ptr: AscPtr<AscString>,
+        fn_args_ptr: u32,
+        return_value_ptr: u32,
+        reverts_ptr: AscPtr<bool>,
+    ) -> Result<(), HostExportError> {
+        let contract_name: String = asc_get(&self.wasm_ctx, contract_name_ptr, &GasCounter::new())?;
         let contract_address: Address = asc_get(
             &self.wasm_ctx,
             contract_address_ptr.into(),
             &GasCounter::new(),
         )?;
-        let fn_name: String = asc_get(&self.wasm_ctx, fn_name_ptr, &GasCounter::new())?;
         let fn_signature: String = asc_get(&self.wasm_ctx, fn_signature_ptr, &GasCounter::new())?;
         let fn_args: Vec<Token> = asc_get::<_, Array<AscPtr<AscEnum<EthereumValueKind>>>, _>(
             &self.wasm_ctx,
@@ -844,47 +1123,87 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         )?;
         let reverts = bool::from(EnumPayload(reverts_ptr.to_payload()));
 
-        let tmp_str = fn_signature.replace(&(fn_name.clone() + "("), "");
-        let components: Vec<&str> = tmp_str.split("):").collect();
-        let tmp_args_str = components[0];
-        let arg_types: Vec<String> = collect_types(tmp_args_str);
-
-        if arg_types.len() != fn_args.len() {
+        let function = self
+            .contract_abi(&contract_name)
+            .and_then(|contract| abis::find_function(&contract_name, contract, &fn_signature))
+            .map(Clone::clone)
+            .unwrap_or_else(|err| logging::critical!("(mockContractFunction) {}", err));
+        abis::check_mocked_call(
+            &function,
+            &fn_args,
+            (!reverts).then(|| return_value.as_slice()),
+        )
+        .unwrap_or_else(|err| {
             logging::critical!(
-                "{} expected {} arguments, but received {}",
-                fn_name,
-                arg_types.len(),
-                fn_args.len()
+                "(mockContractFunction) `{}` of '{}': {}",
+                abis::call_signature(&function),
+                contract_name,
+                err
             )
-        }
-
-        for (index, (arg_type, fn_arg)) in arg_types.iter().zip(fn_args.iter()).enumerate() {
-            let param_type = get_kind(arg_type.to_owned());
-
-            if !fn_arg.type_check(&param_type) {
-                logging::critical!(
-                    "createMockedFunction `{}` parameters mismatch at position {}:\nExpected: {:?}\nRecieved: {:?}\n",
-                    fn_name,
-                    index + 1,
-                    param_type,
-                    fn_arg
-                );
-            }
-        }
+        });
 
-        let fn_id = MatchstickInstanceContext::<C>::fn_id(
-            &contract_address.to_string(),
-            &fn_name,
-            &fn_signature,
+        let fn_id = self.register_mocked_function(
+            contract_address,
+            function.name.clone(),
+            abis::call_signature(&function),
             fn_args,
         );
+        self.fn_ret_map
+            .insert(fn_id, CallOutcome::mocked(return_value, reverts));
 
-        if reverts {
-            self.fn_ret_map.insert(fn_id, REVERTS_IDENTIFIER.clone());
-        } else {
-            self.fn_ret_map.insert(fn_id, return_value);
-        }
+        Ok(())
+    }
+
+    /// function setUnmockedCallPolicy(policy: string): void
+    pub fn set_unmocked_call_policy(
+        &mut self,
+        _gas: &GasCounter,
+        policy_ptr: AscPtr<AscString>,
+    ) -> Result<(), HostExportError> {
+        let policy: String = asc_get(&self.wasm_ctx, policy_ptr, &GasCounter::new())?;
 
+        self.unmocked_call_policy = UnmockedCallPolicy::parse(&policy)
+            .unwrap_or_else(|err| logging::critical!("(setUnmockedCallPolicy) {}", err));
+        Ok(())
+    }
+
+    /// function mockFunctionMatching(
+    ///     contractAddress: string, fnName: string, fnSignature: string,
+    ///     argMatchers: Array<string>, returnValue: ethereum.Value[], reverts: bool,
+    /// ): void
+    #[allow(clippy::too_many_arguments)]
+    pub fn mock_function_matching(
+        &mut self,
+        _gas: &GasCounter,
+        contract_address_ptr: AscPtr<AscString>,
+        fn_name_ptr: AscPtr<AscString>,
+        fn_signature_ptr: AscPtr<AscString>,
+        arg_matchers_ptr: AscPtr<Array<AscPtr<AscString>>>,
+        return_value_ptr: u32,
+        reverts_ptr: AscPtr<bool>,
+    ) -> Result<(), HostExportError> {
+        let contract_address: String =
+            asc_get(&self.wasm_ctx, contract_address_ptr, &GasCounter::new())?;
+        let fn_name: String = asc_get(&self.wasm_ctx, fn_name_ptr, &GasCounter::new())?;
+        let fn_signature: String = asc_get(&self.wasm_ctx, fn_signature_ptr, &GasCounter::new())?;
+        let arg_matchers: Vec<String> =
+            asc_get(&self.wasm_ctx, arg_matchers_ptr, &GasCounter::new())?;
+        let return_value: Vec<Token> = asc_get::<_, Array<AscPtr<AscEnum<EthereumValueKind>>>, _>(
+            &self.wasm_ctx,
+            return_value_ptr.into(),
+            &GasCounter::new(),
+        )?;
+        let reverts = bool::from(EnumPayload(reverts_ptr.to_payload()));
+
+        let pattern =
+            mock_calls::parse_call_pattern(&contract_address, fn_name, fn_signature, &arg_matchers)
+                .unwrap_or_else(|err| logging::critical!("(mockFunctionMatching) {}", err));
+
+        self.register_mock(MockId::Matcher(self.call_matchers.len()));
+        self.call_matchers.push(CallMatcher {
+            pattern,
+            outcome: CallOutcome::mocked(return_value, reverts),
+        });
         Ok(())
     }
 
@@ -892,9 +1211,17 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
     pub fn mock_data_source_create(
         &mut self,
         _gas: &GasCounter,
-        _name_ptr: AscPtr<AscString>,
-        _params_ptr: AscPtr<Array<AscPtr<AscString>>>,
+        name_ptr: AscPtr<AscString>,
+        params_ptr: AscPtr<Array<AscPtr<AscString>>>,
     ) -> Result<(), HostExportError> {
+        let name: String = asc_get(&self.wasm_ctx, name_ptr, &GasCounter::new())?;
+        let params: Vec<String> = asc_get(&self.wasm_ctx, params_ptr, &GasCounter::new())?;
+
+        self.data_sources.push(CreatedDataSource {
+            name,
+            params,
+            block: self.block.clone(),
+        });
         Ok(())
     }
 
@@ -904,12 +1231,12 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
     /// ): void
     pub fn mock_data_source_create_with_context(
         &mut self,
-        _gas: &GasCounter,
-        _name_ptr: AscPtr<AscString>,
-        _params_ptr: AscPtr<Array<AscPtr<AscString>>>,
+        gas: &GasCounter,
+        name_ptr: AscPtr<AscString>,
+        params_ptr: AscPtr<Array<AscPtr<AscString>>>,
         _context_ptr: AscPtr<AscEntity>,
     ) -> Result<(), HostExportError> {
-        Ok(())
+        self.mock_data_source_create(gas, name_ptr, params_ptr)
     }
 
     /// function dataSource.address(): Address
@@ -1009,16 +1336,513 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
     ) -> Result<i32, HostExportError> {
         let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
 
-        match self.store.get(&entity_type) {
-            Some(inner_map) => Ok(inner_map.len().try_into().unwrap_or_else(|err| {
-                panic!(
-                    "Couldn't cast usize value: {} into i32.\n{}",
-                    inner_map.len(),
+        let count = self
+            .entities()
+            .get(&entity_type)
+            .map_or(0, |entities| entities.len());
+        Ok(count.try_into().unwrap_or_else(|err| {
+            panic!("Couldn't cast usize value: {} into i32.\n{}", count, err)
+        }))
+    }
+
+    /// function queryEntities(entityType: string, query: string): Array<string>
+    pub fn query_entities(
+        &mut self,
+        _gas: &GasCounter,
+        entity_type_ptr: AscPtr<AscString>,
+        query_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<Array<AscPtr<AscString>>>, HostExportError> {
+        let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
+        let query: String = asc_get(&self.wasm_ctx, query_ptr, &GasCounter::new())?;
+
+        let query = serde_json::from_str(&query)
+            .map_err(|err| err.to_string())
+            .and_then(|json| query::parse_query(&entity_type, &json))
+            .unwrap_or_else(|err| {
+                logging::critical!("(queryEntities) Invalid query `{}`: {}", query, err)
+            });
+        let ids: Vec<String> = self
+            .query_store(&entity_type, &query, None)
+            .into_iter()
+            .map(|(_, id, _)| id)
+            .collect();
+
+        let result = asc_new(&mut self.wasm_ctx, ids.as_slice(), &GasCounter::new())?;
+        Ok(result)
+    }
+
+    /// function _assert.entityCount(entityType: string, expectedCount: i32): bool
+    pub fn assert_entity_count(
+        &mut self,
+        _gas: &GasCounter,
+        entity_type_ptr: AscPtr<AscString>,
+        expected_count: u32,
+    ) -> Result<bool, HostExportError> {
+        let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
+        let expected_count =
+            values::non_negative("assert.entityCount", "Expected count", expected_count);
+
+        let count = self
+            .entities()
+            .get(&entity_type)
+            .map_or(0, |entities| entities.len());
+        if count != expected_count as usize {
+            logging::error!(
+                "(assert.entityCount) Expected {} entities of type '{}', but found {}.",
+                expected_count,
+                entity_type,
+                count
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// function _assert.entitiesWhere(entityType: string, filter: string, expectedCount: i32): bool
+    pub fn assert_entities_where(
+        &mut self,
+        _gas: &GasCounter,
+        entity_type_ptr: AscPtr<AscString>,
+        filter_ptr: AscPtr<AscString>,
+        expected_count: u32,
+    ) -> Result<bool, HostExportError> {
+        let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
+        let filter: String = asc_get(&self.wasm_ctx, filter_ptr, &GasCounter::new())?;
+        let expected_count =
+            values::non_negative("assert.entitiesWhere", "Expected count", expected_count);
+
+        let query = serde_json::from_str(&filter)
+            .map_err(|err| err.to_string())
+            .and_then(|json| query::parse_filters(&entity_type, &json))
+            .unwrap_or_else(|err| {
+                logging::critical!(
+                    "(assert.entitiesWhere) Invalid filter `{}`: {}",
+                    filter,
                     err
                 )
-            })),
-            None => Ok(0),
+            });
+        let ids: Vec<String> = self
+            .query_store(&entity_type, &query, None)
+            .into_iter()
+            .map(|(_, id, _)| id)
+            .collect();
+
+        if ids.len() != expected_count as usize {
+            logging::error!(
+                "(assert.entitiesWhere) Expected {} entities of type '{}' matching `{}`, but found {}: [{}]",
+                expected_count,
+                entity_type,
+                filter,
+                ids.len(),
+                ids.join(", ")
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// function queryGraphQL(query: string, variables: string): string
+    pub fn query_graphql(
+        &mut self,
+        _gas: &GasCounter,
+        query_ptr: AscPtr<AscString>,
+        variables_ptr: AscPtr<AscString>,
+    ) -> Result<AscPtr<AscString>, HostExportError> {
+        let query: String = asc_get(&self.wasm_ctx, query_ptr, &GasCounter::new())?;
+        let variables: String = asc_get(&self.wasm_ctx, variables_ptr, &GasCounter::new())?;
+
+        let response = self.execute_graphql(&query, &parse_variables("queryGraphQL", &variables));
+        let response = to_string_pretty(&response).unwrap_or_else(|err| logging::critical!(err));
+
+        let result = AscPtr::alloc_obj(
+            asc_string_from_str(&response),
+            &mut self.wasm_ctx,
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create pointer.");
+        Ok(result)
+    }
+
+    /// function _assert.graphQLResultEquals(query: string, variables: string, expected: string): bool
+    pub fn assert_graphql_result_equals(
+        &mut self,
+        _gas: &GasCounter,
+        query_ptr: AscPtr<AscString>,
+        variables_ptr: AscPtr<AscString>,
+        expected_ptr: AscPtr<AscString>,
+    ) -> Result<bool, HostExportError> {
+        let query: String = asc_get(&self.wasm_ctx, query_ptr, &GasCounter::new())?;
+        let variables: String = asc_get(&self.wasm_ctx, variables_ptr, &GasCounter::new())?;
+        let expected: String = asc_get(&self.wasm_ctx, expected_ptr, &GasCounter::new())?;
+
+        let expected: serde_json::Value = serde_json::from_str(&expected).unwrap_or_else(|err| {
+            logging::critical!(
+                "(assert.graphQLResultEquals) Expected result is not valid JSON: {}",
+                err
+            )
+        });
+        let mut response = self.execute_graphql(
+            &query,
+            &parse_variables("assert.graphQLResultEquals", &variables),
+        );
+
+        if let Some(errors) = response.get("errors") {
+            logging::error!("(assert.graphQLResultEquals) Query failed: {}", errors);
+            return Ok(false);
+        }
+
+        // The expected result can be either the whole response or just its `data`.
+        let is_response = expected
+            .as_object()
+            .map_or(false, |o| o.len() == 1 && o.contains_key("data"));
+        let actual = if is_response {
+            response
+        } else {
+            response["data"].take()
+        };
+
+        if actual != expected {
+            logging::error!(
+                "(assert.graphQLResultEquals) Expected result:\n{}\nbut got:\n{}",
+                to_string_pretty(&expected).unwrap_or_default(),
+                to_string_pretty(&actual).unwrap_or_default()
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// function mockBlock(blockNumber: i32, event: string): void
+    pub fn mock_block(
+        &mut self,
+        _gas: &GasCounter,
+        block_number: u32,
+        event_ptr: AscPtr<AscString>,
+    ) -> Result<(), HostExportError> {
+        let event: String = asc_get(&self.wasm_ctx, event_ptr, &GasCounter::new())?;
+        let block_number = values::non_negative("mockBlock", "Block number", block_number);
+
+        // The entity cache is flushed at the end of every block, like in graph-node.
+        if block_number != self.block.number {
+            self.flush_cached_changes();
+        }
+
+        self.block = MockBlock {
+            number: block_number,
+            event: Some(event).filter(|event| !event.is_empty()),
+        };
+        Ok(())
+    }
+
+    /// function useEntityCache(enabled: bool): void
+    pub fn use_entity_cache(
+        &mut self,
+        _gas: &GasCounter,
+        enabled: AscPtr<bool>,
+    ) -> Result<(), HostExportError> {
+        let enabled = bool::from(EnumPayload(enabled.to_payload()));
+
+        self.enable_entity_cache(enabled);
+        Ok(())
+    }
+
+    /// function mergeStoreSet(enabled: bool): void
+    pub fn merge_store_set(
+        &mut self,
+        _gas: &GasCounter,
+        enabled: AscPtr<bool>,
+    ) -> Result<(), HostExportError> {
+        self.merge_store_set = bool::from(EnumPayload(enabled.to_payload()));
+        Ok(())
+    }
+
+    /// function flushEntityCache(): void
+    pub fn flush_entity_cache(&mut self, _gas: &GasCounter) -> Result<(), HostExportError> {
+        self.flush_cached_changes();
+        Ok(())
+    }
+
+    /// function getEntityAtBlock(entityType: string, id: string, blockNumber: i32): Entity
+    pub fn get_entity_at_block(
+        &mut self,
+        _gas: &GasCounter,
+        entity_type_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+        block_number: u32,
+    ) -> Result<AscPtr<AscEntity>, HostExportError> {
+        let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
+        let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
+        let block_number = values::non_negative("getEntityAtBlock", "Block number", block_number);
+
+        match self.entity_at_block(&entity_type, &id, block_number) {
+            Some(entity) => {
+                let entity = Entity::from(entity);
+                let res = asc_new(&mut self.wasm_ctx, &entity.sorted(), &GasCounter::new())?;
+                Ok(res)
+            }
+            None => Ok(AscPtr::null()),
+        }
+    }
+
+    /// function logEntityHistory(entityType: string, id: string): void
+    pub fn log_entity_history(
+        &mut self,
+        _gas: &GasCounter,
+        entity_type_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+    ) -> Result<(), HostExportError> {
+        let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
+        let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
+
+        logging::debug!(
+            "History of entity with type '{}' and id '{}':\n{}",
+            entity_type,
+            id,
+            self.describe_history(&entity_type, &id)
+        );
+        Ok(())
+    }
+
+    /// function _assert.entityChangeCount(entityType: string, id: string, expectedCount: i32): bool
+    pub fn assert_entity_change_count(
+        &mut self,
+        _gas: &GasCounter,
+        entity_type_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+        expected_count: u32,
+    ) -> Result<bool, HostExportError> {
+        let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
+        let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
+        let expected_count =
+            values::non_negative("assert.entityChangeCount", "Expected count", expected_count);
+
+        let count = self.entity_history(&entity_type, &id).len();
+        if count != expected_count as usize {
+            logging::error!(
+                "(assert.entityChangeCount) Expected {} changes to entity with type '{}' and id '{}', but found {}:\n{}",
+                expected_count,
+                entity_type,
+                id,
+                count,
+                self.describe_history(&entity_type, &id)
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// function _assert.fieldHistory(
+    ///     entityType: string, id: string,
+    ///     fieldName: string, expectedValues: Array<string>,
+    /// ): bool
+    pub fn assert_field_history(
+        &mut self,
+        _gas: &GasCounter,
+        entity_type_ptr: AscPtr<AscString>,
+        id_ptr: AscPtr<AscString>,
+        field_name_ptr: AscPtr<AscString>,
+        expected_values_ptr: AscPtr<Array<AscPtr<AscString>>>,
+    ) -> Result<bool, HostExportError> {
+        let entity_type: String = asc_get(&self.wasm_ctx, entity_type_ptr, &GasCounter::new())?;
+        let id: String = asc_get(&self.wasm_ctx, id_ptr, &GasCounter::new())?;
+        let id = entity_schema::id_key(&entity_type, &id);
+        let field_name: String = asc_get(&self.wasm_ctx, field_name_ptr, &GasCounter::new())?;
+        let expected_values: Vec<String> =
+            asc_get(&self.wasm_ctx, expected_values_ptr, &GasCounter::new())?;
+
+        // The value of the field after every change, `null` once the entity was removed.
+        let actual_values: Vec<Value> = self
+            .entity_history(&entity_type, &id)
+            .iter()
+            .map(|change| {
+                change
+                    .current
+                    .as_ref()
+                    .and_then(|entity| entity.get(&field_name))
+                    .cloned()
+                    .unwrap_or(Value::Null)
+            })
+            .collect();
+
+        let matches = actual_values.len() == expected_values.len()
+            && actual_values
+                .iter()
+                .zip(expected_values.iter())
+                .all(|(actual, expected)| {
+                    values::field_matches(&entity_type, &field_name, actual, expected, None)
+                });
+
+        if !matches {
+            logging::error!(
+                "(assert.fieldHistory) Expected field '{}' of entity with type '{}' and id '{}' to be [{}] over time, but was [{}].",
+                field_name,
+                entity_type,
+                id,
+                expected_values.join(", "),
+                actual_values
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// function revertToBlock(blockNumber: i32): void
+    pub fn revert_to_block(
+        &mut self,
+        _gas: &GasCounter,
+        block_number: u32,
+    ) -> Result<(), HostExportError> {
+        let block_number = values::non_negative("revertToBlock", "Block number", block_number);
+        self.revert_store_to_block(block_number);
+        Ok(())
+    }
+
+    /// function _assert.dataSourceCount(templateName: string, expectedCount: i32): bool
+    pub fn assert_data_source_count(
+        &mut self,
+        _gas: &GasCounter,
+        template_name_ptr: AscPtr<AscString>,
+        expected_count: u32,
+    ) -> Result<bool, HostExportError> {
+        let template_name: String = asc_get(&self.wasm_ctx, template_name_ptr, &GasCounter::new())?;
+
+        let created: Vec<String> = self
+            .data_sources
+            .iter()
+            .filter(|data_source| data_source.name == template_name)
+            .map(|data_source| {
+                format!(
+                    "{}({}) at block {}",
+                    data_source.name,
+                    data_source.params.join(", "),
+                    data_source.block.number
+                )
+            })
+            .collect();
+        if created.len() != expected_count as usize {
+            logging::error!(
+                "(assert.dataSourceCount) Expected {} data sources created from template '{}', but found {}: [{}]",
+                expected_count,
+                template_name,
+                created.len(),
+                created.join(", ")
+            );
+            return Ok(false);
         }
+
+        Ok(true)
+    }
+
+    /// function _assert.contractCalled(
+    ///     contractAddress: string, fnSignature: string, argMatchers: Array<string>, times: i32,
+    /// ): bool
+    pub fn assert_contract_called(
+        &mut self,
+        _gas: &GasCounter,
+        contract_address_ptr: AscPtr<AscString>,
+        fn_signature_ptr: AscPtr<AscString>,
+        arg_matchers_ptr: AscPtr<Array<AscPtr<AscString>>>,
+        times: u32,
+    ) -> Result<bool, HostExportError> {
+        let contract_address: String =
+            asc_get(&self.wasm_ctx, contract_address_ptr, &GasCounter::new())?;
+        let fn_signature: String = asc_get(&self.wasm_ctx, fn_signature_ptr, &GasCounter::new())?;
+        let arg_matchers: Vec<String> =
+            asc_get(&self.wasm_ctx, arg_matchers_ptr, &GasCounter::new())?;
+
+        let fn_name = fn_signature
+            .split('(')
+            .next()
+            .unwrap_or_default()
+            .to_owned();
+        let pattern = mock_calls::parse_call_pattern(
+            &contract_address,
+            fn_name,
+            fn_signature.clone(),
+            &arg_matchers,
+        )
+        .unwrap_or_else(|err| logging::critical!("(assert.contractCalled) {}", err));
+
+        let count = self.verify_calls(&pattern);
+        if count != times as usize {
+            logging::error!(
+                "(assert.contractCalled) Expected {} to be called {} times with [{}], but it was called {} times. Calls made:\n{}",
+                fn_signature,
+                times,
+                arg_matchers.join(", "),
+                count,
+                self.describe_calls()
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// function _assert.noUnexpectedCalls(): bool
+    pub fn assert_no_unexpected_calls(
+        &mut self,
+        _gas: &GasCounter,
+    ) -> Result<bool, HostExportError> {
+        let unexpected: Vec<String> = self
+            .calls
+            .iter()
+            .filter(|call| !call.verified)
+            .map(|call| format!("  {}", call.describe()))
+            .collect();
+
+        if !unexpected.is_empty() {
+            logging::error!(
+                "(assert.noUnexpectedCalls) {} contract calls were not expected by an assert.contractCalled:\n{}",
+                unexpected.len(),
+                unexpected.join("\n")
+            );
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// function loadFixture(fileName: string): void
+    pub fn load_fixture(
+        &mut self,
+        _gas: &GasCounter,
+        file_name_ptr: AscPtr<AscString>,
+    ) -> Result<(), HostExportError> {
+        let file_name: String = asc_get(&self.wasm_ctx, file_name_ptr, &GasCounter::new())?;
+
+        let mut path = PathBuf::new();
+        crate::FIXTURES_LOCATION.with(|location| path = location.borrow().join(&file_name));
+
+        self.insert_fixture(&path);
+        Ok(())
+    }
+
+    /// function loadContractMocks(fileName: string): void
+    pub fn load_contract_mocks(
+        &mut self,
+        _gas: &GasCounter,
+        file_name_ptr: AscPtr<AscString>,
+    ) -> Result<(), HostExportError> {
+        let file_name: String = asc_get(&self.wasm_ctx, file_name_ptr, &GasCounter::new())?;
+
+        let mut path = PathBuf::new();
+        crate::FIXTURES_LOCATION.with(|location| path = location.borrow().join(&file_name));
+
+        self.insert_contract_mocks(&path);
+        Ok(())
     }
 
     /// function mockIpfsFile(hash: string, file_path: string): void
@@ -1086,6 +1910,7 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
         let instance = crate::MatchstickInstance::<C>::from_valid_module_with_ctx(
             valid_module.clone(),
             ctx.derive_with_empty_block_state(),
+            self.store.clone(),
             host_metrics.clone(),
             None,
             experimental_features,
@@ -1105,12 +1930,29 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
                 &GasCounter::new(),
             )?;
 
-            instance.instance_ctx_mut().store = self.store.clone();
             instance.instance_ctx_mut().fn_ret_map = self.fn_ret_map.clone();
+            instance.instance_ctx_mut().call_matchers = self.call_matchers.clone();
+            instance.instance_ctx_mut().abis = self.abis.clone();
+            instance.instance_ctx_mut().mocked_functions = self.mocked_functions.clone();
+            instance.instance_ctx_mut().fn_ret_by_block = self.fn_ret_by_block.clone();
+            instance.instance_ctx_mut().fn_ret_queue = self.fn_ret_queue.clone();
+            instance.instance_ctx_mut().calls = self.calls.clone();
+            instance.instance_ctx_mut().test_mocks = self.test_mocks.clone();
+            instance.instance_ctx_mut().served_mocks = self.served_mocks.clone();
             instance.instance_ctx_mut().derived = self.derived.clone();
+            instance.instance_ctx_mut().block = self.block.clone();
+            instance.instance_ctx_mut().data_sources = self.data_sources.clone();
             instance.instance_ctx_mut().data_source_return_value =
                 self.data_source_return_value.clone();
+            instance.instance_ctx_mut().merge_store_set = self.merge_store_set;
+            instance.instance_ctx_mut().unmocked_call_policy = self.unmocked_call_policy;
+            let entity_cache = self.entity_cache.borrow().is_some();
+            instance
+                .instance_ctx_mut()
+                .enable_entity_cache(entity_cache);
 
+            // The callback reads and writes the store shared with this instance.
+            self.flush_cached_changes();
             instance
                 .instance
                 .get_func(&callback)
@@ -1118,85 +1960,44 @@ impl<C: Blockchain> MatchstickInstanceContext<C> {
                 .typed()?
                 .call((value_ptr.wasm_ptr(), data_ptr.wasm_ptr()))
                 .with_context(|| format!("Failed to handle callback '{}'", &callback))?;
+            instance.instance_ctx().flush_cached_changes();
 
-            self.store = instance.instance_ctx().store.clone();
             self.fn_ret_map = instance.instance_ctx().fn_ret_map.clone();
+            self.call_matchers = instance.instance_ctx().call_matchers.clone();
+            self.mocked_functions = instance.instance_ctx().mocked_functions.clone();
+            self.fn_ret_by_block = instance.instance_ctx().fn_ret_by_block.clone();
+            self.fn_ret_queue = instance.instance_ctx().fn_ret_queue.clone();
+            self.calls = instance.instance_ctx().calls.clone();
+            self.test_mocks = instance.instance_ctx().test_mocks.clone();
+            self.served_mocks = instance.instance_ctx().served_mocks.clone();
             self.derived = instance.instance_ctx().derived.clone();
+            self.data_sources = instance.instance_ctx().data_sources.clone();
             self.data_source_return_value =
                 instance.instance_ctx().data_source_return_value.clone();
+            self.merge_store_set = instance.instance_ctx().merge_store_set;
+            self.unmocked_call_policy = instance.instance_ctx().unmocked_call_policy;
+            let entity_cache = instance.instance_ctx().entity_cache.borrow().is_some();
+            self.enable_entity_cache(entity_cache);
         }
 
         Ok(())
     }
+}
 
-    /// This function reads the schema.graphql file and writes all the relations
-    /// between the entities to the derived data structure
-    fn derive_schema(&mut self) {
-        SCHEMA.definitions.iter().for_each(|def| {
-            if let schema::Definition::TypeDefinition(schema::TypeDefinition::Object(o)) = def {
-                let derived_fields = o.fields.iter().filter(|&f| {
-                    matches!(f.field_type, schema::Type::NonNullType(..)) && f.is_derived()
-                });
-                for f in derived_fields {
-                    // field type is received as: '[ExampleClass!]!' and needs to be reduced to a class string
-                    let clean_field_type = f
-                        .field_type
-                        .to_string()
-                        .replace('!', "")
-                        .replace('[', "")
-                        .replace(']', "");
-                    let mut directive = f.find_directive("derivedFrom").unwrap().clone();
-
-                    if self.derived.contains_key(&clean_field_type) {
-                        let mut field_names_vec = self
-                            .derived
-                            .get(&clean_field_type)
-                            .unwrap_or_else(|| {
-                                logging::critical!(
-                                    "Failed to get field names vector for type {}",
-                                    clean_field_type
-                                )
-                            })
-                            .1
-                            .clone();
-
-                        let field_names_tuple = (
-                            f.name.clone(),
-                            directive
-                                .arguments
-                                .pop()
-                                .unwrap()
-                                .1
-                                .to_string()
-                                .replace('\"', ""),
-                        );
-                        if !field_names_vec.contains(&field_names_tuple) {
-                            field_names_vec.push(field_names_tuple);
-                            self.derived
-                                .insert(clean_field_type, (o.name.clone(), field_names_vec));
-                        }
-                    } else {
-                        self.derived.insert(
-                            clean_field_type,
-                            (
-                                o.name.clone(),
-                                vec![(
-                                    f.name.clone(),
-                                    directive
-                                        .arguments
-                                        .pop()
-                                        .unwrap()
-                                        .1
-                                        .to_string()
-                                        .replace('\"', ""),
-                                )],
-                            ),
-                        );
-                    }
-                }
-            }
-        });
+/// Reads the JSON variables passed to a GraphQL query, where an empty string means no variables.
+fn parse_variables(function_name: &str, variables: &str) -> serde_json::Value {
+    if variables.trim().is_empty() {
+        return serde_json::Value::Null;
     }
+
+    serde_json::from_str(variables).unwrap_or_else(|err| {
+        logging::critical!(
+            "({}) Variables `{}` are not valid JSON: {}",
+            function_name,
+            variables,
+            err
+        )
+    })
 }
 
 pub fn asc_string_from_str(initial_string: &str) -> AscString {
@@ -0,0 +1,175 @@
+use std::fs::File;
+
+use graph::{
+    blockchain::Blockchain,
+    prelude::ethabi::{Contract, Function, Param, Token},
+};
+
+use super::MatchstickInstanceContext;
+
+fn param_types(params: &[Param]) -> String {
+    params
+        .iter()
+        .map(|param| param.kind.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// The signature of a function the way the generated contract bindings pass it to
+/// `ethereum.call`, with the return types: `getGravatar(address):(string,string)`.
+pub(crate) fn call_signature(function: &Function) -> String {
+    format!(
+        "{}({}):({})",
+        function.name,
+        param_types(&function.inputs),
+        param_types(&function.outputs)
+    )
+}
+
+/// The function signature without its return types: `getGravatar(address)`.
+fn input_signature(function: &Function) -> String {
+    format!("{}({})", function.name, param_types(&function.inputs))
+}
+
+/// Finds a function by its name, or by its signature when it is overloaded.
+/// The signature can be written with or without its return types.
+pub(crate) fn find_function<'a>(
+    contract_name: &str,
+    contract: &'a Contract,
+    function: &str,
+) -> Result<&'a Function, String> {
+    let name = function.split('(').next().unwrap_or_default();
+    let overloads = contract.functions_by_name(name).map_err(|_| {
+        let mut names: Vec<&str> = contract.functions().map(|f| f.name.as_str()).collect();
+        names.sort_unstable();
+        names.dedup();
+        format!(
+            "Function '{}' is not in the ABI of '{}'. Its functions are: {}",
+            name,
+            contract_name,
+            names.join(", ")
+        )
+    })?;
+
+    let found: Vec<&Function> = overloads
+        .iter()
+        .filter(|f| {
+            !function.contains('(')
+                || function == input_signature(f)
+                || function == call_signature(f)
+        })
+        .collect();
+
+    match found.as_slice() {
+        [found] => Ok(*found),
+        _ => {
+            let signatures: Vec<String> = overloads.iter().map(call_signature).collect();
+            Err(if found.is_empty() {
+                format!(
+                    "'{}' does not match any '{}' function in the ABI of '{}': {}",
+                    function,
+                    name,
+                    contract_name,
+                    signatures.join(", ")
+                )
+            } else {
+                format!(
+                    "'{}' is overloaded in the ABI of '{}', pass the signature of one of: {}",
+                    name,
+                    contract_name,
+                    signatures.join(", ")
+                )
+            })
+        }
+    }
+}
+
+fn check_tokens(kind: &str, params: &[Param], tokens: &[Token]) -> Result<(), String> {
+    if params.len() != tokens.len() {
+        return Err(format!(
+            "expected {} {}, but received {}",
+            params.len(),
+            kind,
+            tokens.len()
+        ));
+    }
+
+    for (index, (param, token)) in params.iter().zip(tokens).enumerate() {
+        if !token.type_check(&param.kind) {
+            return Err(format!(
+                "{} mismatch at position {}: expected `{}`, but received {:?}",
+                kind,
+                index + 1,
+                param.kind,
+                token
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the arguments and, unless the call reverts, the return values of a mocked call
+/// against the inputs and outputs of the function in the ABI.
+pub(crate) fn check_mocked_call(
+    function: &Function,
+    args: &[Token],
+    return_value: Option<&[Token]>,
+) -> Result<(), String> {
+    check_tokens("arguments", &function.inputs, args)?;
+    match return_value {
+        Some(return_value) => check_tokens("return values", &function.outputs, return_value),
+        None => Ok(()),
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Loads an ABI referenced by the subgraph manifest, by the name it is declared with.
+    /// ABIs are only read once.
+    pub(crate) fn contract_abi(&mut self, contract_name: &str) -> Result<&Contract, String> {
+        if !self.abis.contains_key(contract_name) {
+            let path = crate::ABI_FILES
+                .with(|files| files.borrow().get(contract_name).cloned())
+                .ok_or_else(|| {
+                    let mut names: Vec<String> =
+                        crate::ABI_FILES.with(|files| files.borrow().keys().cloned().collect());
+                    names.sort();
+                    format!(
+                        "No ABI named '{}' in subgraph.yaml. The ABIs are: {}",
+                        contract_name,
+                        names.join(", ")
+                    )
+                })?;
+
+            let contract = File::open(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|file| Contract::load(file).map_err(|err| err.to_string()))
+                .map_err(|err| format!("Could not load the ABI {:?}: {}", path, err))?;
+            self.abis.insert(contract_name.to_owned(), contract);
+        }
+
+        Ok(&self.abis[contract_name])
+    }
+
+    /// Whether a function with the passed signature is in any of the ABIs of the manifest.
+    /// ABIs that can't be loaded are skipped, so `None` means none of them could be checked.
+    pub(crate) fn signature_in_abis(&mut self, fn_signature: &str) -> Option<bool> {
+        let names: Vec<String> =
+            crate::ABI_FILES.with(|files| files.borrow().keys().cloned().collect());
+        let mut checked = false;
+
+        for name in names {
+            if let Ok(contract) = self.contract_abi(&name) {
+                checked = true;
+                if contract
+                    .functions()
+                    .any(|function| call_signature(function) == fn_signature)
+                {
+                    return Some(true);
+                }
+            }
+        }
+
+        checked.then(|| false)
+    }
+}
@@ -0,0 +1,296 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use graph::{
+    blockchain::Blockchain,
+    prelude::ethabi::{Address, Function, Param, ParamType, Token},
+};
+use serde_json::Value as JsonValue;
+
+use super::{
+    abis,
+    fixtures::read_fixture,
+    mock_calls::tokenize,
+    reverts::{custom_error, CallOutcome, RevertData},
+    unmocked_calls::input_types,
+    MatchstickInstanceContext,
+};
+use crate::logging;
+
+/// A contract call mocked by a fixture.
+struct MockedCall {
+    contract_address: Address,
+    function: Function,
+    args: Vec<Token>,
+    outcome: CallOutcome,
+}
+
+fn tokens_of<'a>(
+    param_types: impl Iterator<Item = &'a ParamType>,
+    values: &[JsonValue],
+) -> Result<Vec<Token>, String> {
+    param_types
+        .zip(values)
+        .map(|(param_type, value)| token_from_json(param_type, value))
+        .collect()
+}
+
+/// Converts a fixture value to a token of the ABI type. Scalars are written like in
+/// `ethereum.Value` and integers that don't fit in a YAML number can be passed as strings.
+/// Arrays and tuples are lists.
+fn token_from_json(param_type: &ParamType, json: &JsonValue) -> Result<Token, String> {
+    match (param_type, json) {
+        (ParamType::String, JsonValue::String(text)) => Ok(Token::String(text.clone())),
+        (ParamType::Array(inner), JsonValue::Array(values)) => {
+            tokens_of(std::iter::repeat(inner.as_ref()), values).map(Token::Array)
+        }
+        (ParamType::FixedArray(inner, size), JsonValue::Array(values)) if values.len() == *size => {
+            tokens_of(std::iter::repeat(inner.as_ref()), values).map(Token::FixedArray)
+        }
+        (ParamType::Tuple(components), JsonValue::Array(values))
+            if values.len() == components.len() =>
+        {
+            tokens_of(components.iter(), values).map(Token::Tuple)
+        }
+        (
+            ParamType::String
+            | ParamType::Array(_)
+            | ParamType::FixedArray(..)
+            | ParamType::Tuple(_),
+            _,
+        ) => Err(format!("{} is not a valid `{}`", json, param_type)),
+        (_, JsonValue::String(text)) => tokenize(param_type, text),
+        (_, JsonValue::Number(_) | JsonValue::Bool(_)) => tokenize(param_type, &json.to_string()),
+        _ => Err(format!("{} is not a valid `{}`", json, param_type)),
+    }
+}
+
+fn tokens_from_json(kind: &str, params: &[Param], json: &JsonValue) -> Result<Vec<Token>, String> {
+    let values = json
+        .as_array()
+        .ok_or_else(|| format!("`{}` should be a list", kind))?;
+    if values.len() != params.len() {
+        return Err(format!(
+            "expected {} {}, but received {}",
+            params.len(),
+            kind,
+            values.len()
+        ));
+    }
+
+    params
+        .iter()
+        .zip(values)
+        .enumerate()
+        .map(|(index, (param, value))| {
+            token_from_json(&param.kind, value)
+                .map_err(|err| format!("{} {}: {}", kind, index + 1, err))
+        })
+        .collect()
+}
+
+/// Reads the custom error a call reverts with, written as its signature and arguments
+/// like `{ error: "NotOwner(address)", args: ["0x90cB…1947"] }`.
+fn custom_error_from_json(
+    error: &serde_json::Map<String, JsonValue>,
+) -> Result<RevertData, String> {
+    if let Some(key) = error
+        .keys()
+        .find(|key| !["error", "args"].contains(&key.as_str()))
+    {
+        return Err(format!("unknown key `{}` in `reverts`", key));
+    }
+
+    let error_signature = error
+        .get("error")
+        .and_then(JsonValue::as_str)
+        .ok_or("`reverts` should have the `error` signature, like `NotOwner(address)`")?;
+    let values = match error.get("args") {
+        Some(JsonValue::Array(values)) => values.as_slice(),
+        Some(_) => return Err("`args` of `reverts` should be a list".to_owned()),
+        None => &[],
+    };
+    let param_types = input_types(error_signature);
+    if values.len() != param_types.len() {
+        return Err(format!(
+            "{} expected {} arguments, but received {}",
+            error_signature,
+            param_types.len(),
+            values.len()
+        ));
+    }
+
+    let error_args = tokens_of(param_types.iter(), values)?;
+    custom_error(error_signature, &error_args)
+}
+
+/// Reads the arguments and the outcome of a mocked call, checked against the ABI. A call
+/// `returns` values, `reverts` with `true`, a reason or a custom error, or `fails` with a message.
+fn parse_call(function: &Function, call: &JsonValue) -> Result<(Vec<Token>, CallOutcome), String> {
+    let call = call
+        .as_object()
+        .ok_or("a call should be a map with `args` and one of `returns`, `reverts` or `fails`")?;
+    if let Some(key) = call
+        .keys()
+        .find(|key| !["args", "returns", "reverts", "fails"].contains(&key.as_str()))
+    {
+        return Err(format!("unknown key `{}`", key));
+    }
+
+    let args = tokens_from_json(
+        "args",
+        &function.inputs,
+        call.get("args").unwrap_or(&JsonValue::Array(vec![])),
+    )?;
+
+    let outcome = match (call.get("returns"), call.get("reverts"), call.get("fails")) {
+        (Some(returns), None | Some(JsonValue::Bool(false)), None) => {
+            CallOutcome::Returned(tokens_from_json("returns", &function.outputs, returns)?)
+        }
+        (None, Some(JsonValue::Bool(true)), None) => CallOutcome::Reverted(None),
+        (None, Some(JsonValue::String(reason)), None) => {
+            CallOutcome::Reverted(Some(RevertData::Reason(reason.clone())))
+        }
+        (None, Some(JsonValue::Object(error)), None) => {
+            CallOutcome::Reverted(Some(custom_error_from_json(error)?))
+        }
+        (None, Some(_), None) => {
+            return Err(
+                "`reverts` should be `true`, the revert reason or a custom error".to_owned(),
+            )
+        }
+        (None, None, Some(JsonValue::String(message))) => CallOutcome::Failed(message.clone()),
+        (None, None, Some(_)) => return Err("`fails` should be the failure message".to_owned()),
+        (None, None, None) => return Err("`returns`, `reverts` or `fails` is missing".to_owned()),
+        _ => return Err("only one of `returns`, `reverts` or `fails` can be set".to_owned()),
+    };
+
+    Ok((args, outcome))
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Reads a contract mock fixture, which maps the names of the ABIs in subgraph.yaml to
+    /// contract addresses, functions and their calls. Functions are found by their name,
+    /// or by their signature when they are overloaded. A function maps to a call or a list of calls:
+    /// ```yaml
+    /// Gravity:
+    ///   "0x89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7":
+    ///     getGravatar:
+    ///       - args: ["0x90cBa2Bbb19ecc291A12066Fd8329D65FA1f1947"]
+    ///         returns: ["Gravatar", "https://example.com/gravatar.png"]
+    ///       - args: ["0x0000000000000000000000000000000000000000"]
+    ///         reverts: "No Gravatar"
+    ///     gravatarCount():
+    ///       returns: ["3"]
+    /// ```
+    fn parse_contract_mocks(&mut self, path: &Path) -> Vec<MockedCall> {
+        let fixture = read_fixture(path);
+        let contracts = fixture.as_object().unwrap_or_else(|| {
+            logging::critical!(
+                "Contract mocks `{:?}` should map ABI names to contract addresses.",
+                path
+            )
+        });
+
+        let mut errors: Vec<String> = vec![];
+        let mut calls = vec![];
+
+        for (contract_name, addresses) in contracts {
+            let contract = match self.contract_abi(contract_name) {
+                Ok(contract) => contract,
+                Err(err) => {
+                    errors.push(err);
+                    continue;
+                }
+            };
+
+            let addresses = match addresses.as_object() {
+                Some(addresses) => addresses,
+                None => {
+                    errors.push(format!(
+                        "'{}' should map contract addresses to functions.",
+                        contract_name
+                    ));
+                    continue;
+                }
+            };
+
+            for (address, functions) in addresses {
+                let contract_address = match Address::from_str(address.trim_start_matches("0x")) {
+                    Ok(contract_address) => contract_address,
+                    Err(_) => {
+                        errors.push(format!(
+                            "{}: '{}' is not a valid contract address.",
+                            contract_name, address
+                        ));
+                        continue;
+                    }
+                };
+
+                let functions = match functions.as_object() {
+                    Some(functions) => functions,
+                    None => {
+                        errors.push(format!(
+                            "{}[{}] should map functions to calls.",
+                            contract_name, address
+                        ));
+                        continue;
+                    }
+                };
+
+                for (function_name, function_calls) in functions {
+                    let function = match abis::find_function(contract_name, contract, function_name)
+                    {
+                        Ok(function) => function,
+                        Err(err) => {
+                            errors.push(format!("{}[{}]: {}", contract_name, address, err));
+                            continue;
+                        }
+                    };
+
+                    let function_calls: Vec<&JsonValue> = match function_calls {
+                        JsonValue::Array(function_calls) => function_calls.iter().collect(),
+                        call => vec![call],
+                    };
+                    for (index, call) in function_calls.into_iter().enumerate() {
+                        match parse_call(function, call) {
+                            Ok((args, outcome)) => calls.push(MockedCall {
+                                contract_address,
+                                function: function.clone(),
+                                args,
+                                outcome,
+                            }),
+                            Err(err) => errors.push(format!(
+                                "{}[{}].{}[{}]: {}",
+                                contract_name, address, function_name, index, err
+                            )),
+                        }
+                    }
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            logging::critical!(
+                "Contract mocks `{:?}` do not match the ABIs of subgraph.yaml:\n{}",
+                path,
+                errors.join("\n")
+            );
+        }
+
+        calls
+    }
+
+    /// Mocks the contract calls of a fixture, replacing mocks of the same calls.
+    pub(crate) fn insert_contract_mocks(&mut self, path: &Path) {
+        for call in self.parse_contract_mocks(path) {
+            let fn_id = self.register_mocked_function(
+                call.contract_address,
+                call.function.name.clone(),
+                abis::call_signature(&call.function),
+                call.args,
+            );
+            self.fn_ret_map.insert(fn_id, call.outcome);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use graph::{blockchain::Blockchain, data::graphql::ext::DirectiveFinder, data::store::Value};
+use graph_graphql::graphql_parser::schema;
+
+use super::{entity_schema, MatchstickInstanceContext, SCHEMA};
+use crate::logging;
+
+/// A field declared with `@derivedFrom` on an entity type. The example below
+/// is taken from a schema.graphql file:
+/// ```
+/// type GraphAccount @entity {
+///     id: ID!
+///     nameSignalTransactions: [NameSignalTransaction!]! @derivedFrom(field: "signer")
+/// }
+/// ```
+/// and is described as `field_name: "nameSignalTransactions"`, `entity_type: "NameSignalTransaction"`,
+/// `derived_from: "signer"` and `is_list: true`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct DerivedField {
+    pub(crate) field_name: String,
+    pub(crate) entity_type: String,
+    pub(crate) derived_from: String,
+    pub(crate) is_list: bool,
+}
+
+/// Reads the schema.graphql file and collects the derived fields of every entity type,
+/// keyed by the name of the entity type declaring them.
+pub(crate) fn derive_schema() -> HashMap<String, Vec<DerivedField>> {
+    SCHEMA
+        .definitions
+        .iter()
+        .filter_map(|def| {
+            if let schema::Definition::TypeDefinition(schema::TypeDefinition::Object(o)) = def {
+                let derived_fields: Vec<DerivedField> = o
+                    .fields
+                    .iter()
+                    .filter(|&f| f.is_derived())
+                    .map(|f| {
+                        let derived_from = f
+                            .find_directive("derivedFrom")
+                            .and_then(|directive| {
+                                directive
+                                    .arguments
+                                    .iter()
+                                    .find(|(name, _)| name == "field")
+                                    .map(|(_, value)| value.to_string().replace('\"', ""))
+                            })
+                            .unwrap_or_else(|| {
+                                logging::critical!(
+                                    "Missing `field` argument of @derivedFrom on '{}.{}'.",
+                                    o.name,
+                                    f.name
+                                )
+                            });
+
+                        DerivedField {
+                            field_name: f.name.clone(),
+                            entity_type: entity_schema::base_type_name(&f.field_type),
+                            derived_from,
+                            is_list: entity_schema::is_list_type(&f.field_type),
+                        }
+                    })
+                    .collect();
+
+                if !derived_fields.is_empty() {
+                    return Some((o.name.clone(), derived_fields));
+                }
+            }
+            None
+        })
+        .collect()
+}
+
+/// Whether the value of a relation field points to the entity with the passed id.
+/// Relation fields can hold a single id or a list of ids, of any id type.
+fn references(value: &Value, id: &str) -> bool {
+    match value {
+        Value::List(values) => values.iter().any(|v| references(v, id)),
+        value => entity_schema::reference_key(value).map_or(false, |key| key == id),
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Returns a copy of the entity with its `@derivedFrom` fields resolved from the
+    /// entities currently in the store. Derived fields are never stored, just like in graph-node.
+    pub(crate) fn with_derived_fields(
+        &self,
+        entity_type: &str,
+        id: &str,
+        entity: &HashMap<String, Value>,
+    ) -> HashMap<String, Value> {
+        let derived_fields = match self.derived.get(entity_type) {
+            Some(derived_fields) if !derived_fields.is_empty() => derived_fields,
+            _ => return entity.clone(),
+        };
+        let mut entity = entity.clone();
+        // Pending changes of the entity cache are flushed first, so both modes see them.
+        let store = self.entities();
+
+        for derived_field in derived_fields {
+            let mut ids: Vec<String> = entity_schema::concrete_types(&derived_field.entity_type)
+                .iter()
+                .filter_map(|derived_type| store.get(derived_type))
+                .flat_map(|entities| entities.iter())
+                .filter(|(_, data)| {
+                    data.get(&derived_field.derived_from)
+                        .map_or(false, |value| references(value, id))
+                })
+                .map(|(derived_id, _)| derived_id.clone())
+                .collect();
+            ids.sort();
+
+            // The derived entities are referenced by their ids, with the type of those ids.
+            let id_value = |id: String| entity_schema::id_value(&derived_field.entity_type, &id);
+            let value = if derived_field.is_list {
+                Value::List(ids.into_iter().map(id_value).collect())
+            } else {
+                ids.into_iter().next().map_or(Value::Null, id_value)
+            };
+
+            entity.insert(derived_field.field_name.clone(), value);
+        }
+
+        entity
+    }
+
+    /// Returns a copy of the whole store with all derived fields resolved.
+    pub(crate) fn store_with_derived_fields(
+        &self,
+    ) -> HashMap<String, HashMap<String, HashMap<String, Value>>> {
+        let store = self.entities().clone();
+
+        store
+            .iter()
+            .map(|(entity_type, entities)| {
+                let entities = entities
+                    .iter()
+                    .map(|(id, entity)| {
+                        (
+                            id.clone(),
+                            self.with_derived_fields(entity_type, id, entity),
+                        )
+                    })
+                    .collect();
+                (entity_type.clone(), entities)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,93 @@
+use std::collections::HashMap;
+
+use graph::{blockchain::Blockchain, components::store::EntityCache, data::store::Value};
+
+use super::MatchstickInstanceContext;
+use crate::logging;
+use crate::writable_store::{entity_key, from_entity};
+
+/// Merges the updated fields into the entity, removing the ones set to `null`.
+pub(crate) fn merge_remove_null_fields(
+    entity: &mut HashMap<String, Value>,
+    updates: HashMap<String, Value>,
+) {
+    for (field, value) in updates {
+        match value {
+            Value::Null => entity.remove(&field),
+            value => entity.insert(field, value),
+        };
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Turns graph-node's entity cache on or off. Pending changes are written to the store
+    /// before it is turned off.
+    pub(crate) fn enable_entity_cache(&mut self, enabled: bool) {
+        if !enabled {
+            self.flush_cached_changes();
+            *self.entity_cache.get_mut() = None;
+        } else if self.entity_cache.get_mut().is_none() {
+            *self.entity_cache.get_mut() = Some(EntityCache::new(self.store.clone()));
+        }
+    }
+
+    /// Drops the pending changes, without writing them to the store.
+    pub(crate) fn discard_cached_changes(&mut self) {
+        if let Some(cache) = self.entity_cache.get_mut() {
+            *cache = EntityCache::new(self.store.clone());
+        }
+    }
+
+    pub(crate) fn cache_set(&self, entity_type: &str, id: &str, data: HashMap<String, Value>) {
+        if let Some(cache) = self.entity_cache.borrow_mut().as_mut() {
+            // The change is kept even when graph-node finds the entity invalid, the missing
+            // fields have already been reported.
+            if let Err(err) = cache.set(entity_key(entity_type, id), data.into()) {
+                logging::warning!("(store.set) {}", err);
+            }
+        }
+    }
+
+    pub(crate) fn cache_remove(&self, entity_type: &str, id: &str) {
+        if let Some(cache) = self.entity_cache.borrow_mut().as_mut() {
+            cache.remove(entity_key(entity_type, id));
+        }
+    }
+
+    /// The entity as the mappings see it. With the entity cache on, the pending changes of
+    /// the current block are applied to it, the way graph-node's `store.get` does.
+    pub(crate) fn cached_entity(
+        &self,
+        entity_type: &str,
+        id: &str,
+    ) -> Option<HashMap<String, Value>> {
+        match self.entity_cache.borrow_mut().as_mut() {
+            Some(cache) => cache
+                .get(&entity_key(entity_type, id))
+                .unwrap_or_else(|err| logging::critical!("(store.get) {}", err))
+                .map(|entity| from_entity(&entity)),
+            None => self
+                .store
+                .entities()
+                .get(entity_type)
+                .and_then(|entities| entities.get(id))
+                .cloned(),
+        }
+    }
+
+    /// Writes the pending changes to the store. Called at the end of a block and before
+    /// anything but `store.get` reads the store. Changes that leave an entity as it was
+    /// are dropped, just like graph-node does.
+    pub(crate) fn flush_cached_changes(&self) {
+        let pending = match self.entity_cache.borrow_mut().as_mut() {
+            Some(cache) => std::mem::replace(cache, EntityCache::new(self.store.clone())),
+            None => return,
+        };
+
+        let modifications = pending
+            .as_modifications()
+            .unwrap_or_else(|err| logging::critical!("Could not flush the entity cache: {}", err))
+            .modifications;
+        self.store.apply_modifications(&self.block, modifications);
+    }
+}
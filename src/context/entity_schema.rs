@@ -0,0 +1,248 @@
+use std::str::FromStr;
+
+use graph::{
+    data::store::{scalar::Bytes, Value},
+    prelude::{BigDecimal, BigInt},
+};
+use graph_graphql::graphql_parser::schema;
+use serde_json::Value as JsonValue;
+
+use super::SCHEMA;
+
+/// Finds the definition of the entity type with the passed name in `schema.graphql`.
+pub(crate) fn entity_definition(
+    entity_type: &str,
+) -> Option<&'static schema::ObjectType<'static, String>> {
+    SCHEMA.definitions.iter().find_map(|def| {
+        if let schema::Definition::TypeDefinition(schema::TypeDefinition::Object(o)) = def {
+            if o.name == entity_type {
+                return Some(o);
+            }
+        }
+        None
+    })
+}
+
+/// Finds the definition of a single field of an entity type in `schema.graphql`.
+pub(crate) fn field_definition(
+    entity_type: &str,
+    field_name: &str,
+) -> Option<&'static schema::Field<'static, String>> {
+    entity_definition(entity_type)?
+        .fields
+        .iter()
+        .find(|f| f.name == field_name)
+}
+
+/// Finds the fields of an entity type or an interface in `schema.graphql`.
+pub(crate) fn type_fields(type_name: &str) -> Option<&'static [schema::Field<'static, String>]> {
+    SCHEMA.definitions.iter().find_map(|def| match def {
+        schema::Definition::TypeDefinition(schema::TypeDefinition::Object(o))
+            if o.name == type_name =>
+        {
+            Some(o.fields.as_slice())
+        }
+        schema::Definition::TypeDefinition(schema::TypeDefinition::Interface(i))
+            if i.name == type_name =>
+        {
+            Some(i.fields.as_slice())
+        }
+        _ => None,
+    })
+}
+
+/// The type of the `id` field of an entity type or interface, `ID` when it is not in the schema.
+/// Besides `ID` and `String`, ids can be `Bytes` or `BigInt`.
+pub(crate) fn id_type(type_name: &str) -> String {
+    type_fields(type_name)
+        .and_then(|fields| fields.iter().find(|f| f.name == "id"))
+        .map_or_else(|| "ID".to_owned(), |f| base_type_name(&f.field_type))
+}
+
+/// Entities are stored by their id written as a string, whatever the type of the id.
+/// `Bytes` ids are kept as lower case hex and numeric ids without leading zeros,
+/// so the same id written differently still finds the same entity.
+pub(crate) fn id_key(entity_type: &str, id: &str) -> String {
+    reference_key(&id_value(entity_type, id)).unwrap_or_else(|| id.to_owned())
+}
+
+/// Converts an id written as a string to the value of the `id` field of the entity type.
+/// Ids that can't be parsed as their declared type are kept as strings.
+pub(crate) fn id_value(entity_type: &str, id: &str) -> Value {
+    scalar_from_json(&id_type(entity_type), &JsonValue::String(id.to_owned()))
+        .unwrap_or_else(|_| Value::String(id.to_owned()))
+}
+
+/// The key of the entity a relation field points to, for relations holding an id of any type.
+pub(crate) fn reference_key(value: &Value) -> Option<String> {
+    match value {
+        Value::String(id) => Some(id.clone()),
+        Value::Bytes(id) => Some(id.to_string()),
+        Value::BigInt(id) => Some(id.to_string()),
+        Value::Int(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// Returns the names of all entity types that can be stored under the passed type.
+/// For an entity type that is the type itself, for an interface those are all the
+/// entity types implementing it.
+pub(crate) fn concrete_types(type_name: &str) -> Vec<String> {
+    if entity_definition(type_name).is_some() {
+        return vec![type_name.to_owned()];
+    }
+
+    SCHEMA
+        .definitions
+        .iter()
+        .filter_map(|def| {
+            if let schema::Definition::TypeDefinition(schema::TypeDefinition::Object(o)) = def {
+                if o.implements_interfaces.iter().any(|i| i == type_name) {
+                    return Some(o.name.clone());
+                }
+            }
+            None
+        })
+        .collect()
+}
+
+/// Reduces a field type like `[ExampleClass!]!` to the name of the type it holds.
+pub(crate) fn base_type_name(field_type: &schema::Type<'static, String>) -> String {
+    match field_type {
+        schema::Type::NamedType(name) => name.clone(),
+        schema::Type::ListType(inner) | schema::Type::NonNullType(inner) => base_type_name(inner),
+    }
+}
+
+/// Whether the field type is a list, regardless of its nullability.
+pub(crate) fn is_list_type(field_type: &schema::Type<'static, String>) -> bool {
+    match field_type {
+        schema::Type::NamedType(_) => false,
+        schema::Type::ListType(_) => true,
+        schema::Type::NonNullType(inner) => is_list_type(inner),
+    }
+}
+
+/// Converts a JSON value to a store value of the passed field type.
+/// Numeric scalars are accepted either as JSON numbers or strings, `Bytes` as hex strings
+/// and relations as the id of the referenced entity.
+pub(crate) fn value_from_json(
+    field_type: &schema::Type<'static, String>,
+    json: &JsonValue,
+) -> Result<Value, String> {
+    match field_type {
+        schema::Type::NonNullType(inner) => {
+            if json.is_null() {
+                Err("expected a non-null value".to_owned())
+            } else {
+                value_from_json(inner, json)
+            }
+        }
+        schema::Type::ListType(inner) => match json {
+            JsonValue::Null => Ok(Value::Null),
+            JsonValue::Array(values) => values
+                .iter()
+                .map(|v| value_from_json(inner, v))
+                .collect::<Result<Vec<Value>, String>>()
+                .map(Value::List),
+            _ => Err(format!("expected a list, but got `{}`", json)),
+        },
+        schema::Type::NamedType(name) => scalar_from_json(name, json),
+    }
+}
+
+/// Parses a value of the passed field type the way it is written in assertions,
+/// with lists written as `[first, second]`.
+pub(crate) fn value_from_str(
+    field_type: &schema::Type<'static, String>,
+    text: &str,
+) -> Result<Value, String> {
+    match field_type {
+        schema::Type::NonNullType(inner) => value_from_str(inner, text),
+        schema::Type::ListType(inner) => {
+            let items = text
+                .trim()
+                .strip_prefix('[')
+                .and_then(|t| t.strip_suffix(']'))
+                .ok_or_else(|| format!("`{}` is not a list", text))?;
+
+            if items.trim().is_empty() {
+                return Ok(Value::List(vec![]));
+            }
+
+            items
+                .split(',')
+                .map(|item| value_from_str(inner, item.trim()))
+                .collect::<Result<Vec<Value>, String>>()
+                .map(Value::List)
+        }
+        schema::Type::NamedType(name) => {
+            scalar_from_json(name, &JsonValue::String(text.to_owned()))
+        }
+    }
+}
+
+fn scalar_from_json(type_name: &str, json: &JsonValue) -> Result<Value, String> {
+    if json.is_null() {
+        return Ok(Value::Null);
+    }
+
+    // Numbers can be written both as `1` and `"1"`, so big numbers don't lose precision.
+    let text = match json {
+        JsonValue::String(s) => s.clone(),
+        JsonValue::Number(n) => n.to_string(),
+        JsonValue::Bool(b) => b.to_string(),
+        _ => return Err(format!("expected a `{}`, but got `{}`", type_name, json)),
+    };
+    let invalid = |err: String| format!("`{}` is not a valid `{}`: {}", text, type_name, err);
+
+    match type_name {
+        "Int" => i32::from_str(&text)
+            .map(Value::Int)
+            .map_err(|err| invalid(err.to_string())),
+        "BigInt" => BigInt::from_str(&text)
+            .map(Value::BigInt)
+            .map_err(|err| invalid(err.to_string())),
+        "BigDecimal" => BigDecimal::from_str(&text)
+            .map(Value::BigDecimal)
+            .map_err(|err| invalid(err.to_string())),
+        "Bytes" => Bytes::from_str(&text)
+            .map(Value::Bytes)
+            .map_err(|err| invalid(err.to_string())),
+        "Boolean" => bool::from_str(&text)
+            .map(Value::Bool)
+            .map_err(|err| invalid(err.to_string())),
+        // Relations to entities with `Bytes` or `BigInt` ids hold the id typed like it,
+        // while `ID`, `String`, enums and other relations are all stored as strings.
+        _ => match (relation_id_type(type_name), json) {
+            (Some(id_type), _) => scalar_from_json(&id_type, json),
+            (None, JsonValue::String(s)) => Ok(Value::String(s.clone())),
+            (None, _) => Err(format!("expected a `{}`, but got `{}`", type_name, json)),
+        },
+    }
+}
+
+/// The type of the id a relation to the passed entity type or interface holds,
+/// when that id isn't a string.
+fn relation_id_type(type_name: &str) -> Option<String> {
+    type_fields(type_name)?;
+
+    Some(id_type(type_name)).filter(|id_type| {
+        !matches!(id_type.as_str(), "ID" | "String") && type_fields(id_type).is_none()
+    })
+}
+
+/// Converts a store value to JSON the way `value_from_json` reads it back.
+/// Big numbers are written as strings so they don't lose precision.
+pub(crate) fn value_to_json(value: &Value) -> JsonValue {
+    match value {
+        Value::String(s) => JsonValue::String(s.clone()),
+        Value::Int(i) => JsonValue::from(*i),
+        Value::BigInt(n) => JsonValue::String(n.to_string()),
+        Value::BigDecimal(d) => JsonValue::String(d.to_string()),
+        Value::Bool(b) => JsonValue::Bool(*b),
+        Value::Bytes(bytes) => JsonValue::String(bytes.to_string()),
+        Value::List(values) => JsonValue::Array(values.iter().map(value_to_json).collect()),
+        Value::Null => JsonValue::Null,
+    }
+}
@@ -0,0 +1,282 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use graph::{
+    blockchain::Blockchain,
+    prelude::{
+        ethabi::{self, Address, Token},
+        web3::{self, transports::Http, Transport},
+    },
+};
+use serde_json::{json, Value as JsonValue};
+use tokio::runtime::Runtime;
+
+use super::{
+    mock_calls::to_hex,
+    reverts::{CallOutcome, RevertData},
+    unmocked_calls::{input_types, return_types},
+    MatchstickInstanceContext,
+};
+use crate::logging;
+
+/// How long to wait for the JSON-RPC endpoint before the call fails.
+const RPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An `eth_call` request, identifying a recorded response in the cache.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct EthCallRequest {
+    to: String,
+    data: String,
+    block: String,
+}
+
+/// Forwards contract calls that aren't mocked to a JSON-RPC endpoint, set with `rpcEndpoint`
+/// in matchstick.yaml. Every response is recorded to the `rpcCache` file and later runs replay it,
+/// so the endpoint is only needed for calls that were never made before.
+/// Without an endpoint, only the recorded calls are answered.
+pub struct EthRpc {
+    endpoint: Option<Endpoint>,
+    cache: PathBuf,
+    /// The block calls are made at, `latest` unless `rpcBlock` is set.
+    block: String,
+    responses: BTreeMap<EthCallRequest, JsonValue>,
+}
+
+/// The JSON-RPC endpoint, with the transport and the runtime calls to it are made with.
+struct Endpoint {
+    url: String,
+    transport: Http,
+    runtime: Runtime,
+}
+
+/// The cache is a list of requests with the JSON-RPC response to each:
+/// ```json
+/// [
+///   {
+///     "request": { "to": "0x…", "data": "0x…", "block": "latest" },
+///     "response": { "result": "0x…" }
+///   }
+/// ]
+/// ```
+fn read_cache(path: &Path) -> BTreeMap<EthCallRequest, JsonValue> {
+    if !path.exists() {
+        return BTreeMap::new();
+    }
+
+    let content = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        logging::critical!(
+            "Something went wrong while trying to read `{:?}`: {}",
+            path,
+            err,
+        )
+    });
+    let records: Vec<JsonValue> = serde_json::from_str(&content).unwrap_or_else(|err| {
+        logging::critical!("RPC cache `{:?}` could not be parsed: {}", path, err)
+    });
+
+    records
+        .into_iter()
+        .map(|record| {
+            let field = |name: &str| {
+                record["request"][name]
+                    .as_str()
+                    .map(|value| value.to_owned())
+                    .unwrap_or_else(|| {
+                        logging::critical!(
+                            "RPC cache `{:?}` has a request without `{}`: {}",
+                            path,
+                            name,
+                            record
+                        )
+                    })
+            };
+            let request = EthCallRequest {
+                to: field("to"),
+                data: field("data"),
+                block: field("block"),
+            };
+            (request, record["response"].clone())
+        })
+        .collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    let hex = hex.strip_prefix("0x").unwrap_or(hex);
+    if hex.len() % 2 != 0 {
+        return Err(format!("`{}` has an odd number of digits", hex));
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("`{}` is not a hex string", hex))
+        })
+        .collect()
+}
+
+/// Whether a JSON-RPC error means the call reverted, rather than that it could not be made.
+/// Geth answers reverts with code 3, other nodes with a message mentioning the revert.
+fn is_revert(error: &JsonValue) -> bool {
+    error["code"] == 3
+        || error["message"]
+            .as_str()
+            .map_or(false, |message| message.contains("revert"))
+}
+
+/// What a call reverted with, from the `data` of the JSON-RPC error, or else from the reason
+/// some nodes only put in the message.
+fn revert_data(error: &JsonValue) -> Option<RevertData> {
+    match error["data"].as_str().map(from_hex) {
+        Some(Ok(data)) => RevertData::from_bytes(data),
+        _ => error["message"]
+            .as_str()
+            .and_then(|message| message.split_once("reverted: "))
+            .map(|(_, reason)| RevertData::Reason(reason.to_owned())),
+    }
+}
+
+impl EthRpc {
+    pub fn new(endpoint: Option<String>, cache: PathBuf, block: Option<u64>) -> Self {
+        let endpoint = endpoint.map(|url| {
+            let transport = Http::new(&url).unwrap_or_else(|err| {
+                logging::critical!("`{}` is not a valid RPC endpoint: {}", url, err)
+            });
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap_or_else(|err| logging::critical!(err));
+            Endpoint {
+                url,
+                transport,
+                runtime,
+            }
+        });
+
+        EthRpc {
+            endpoint,
+            responses: read_cache(&cache),
+            cache,
+            block: block.map_or_else(|| "latest".to_owned(), |number| format!("0x{:x}", number)),
+        }
+    }
+
+    fn save(&self) {
+        let records: Vec<JsonValue> = self
+            .responses
+            .iter()
+            .map(|(request, response)| {
+                json!({
+                    "request": { "to": request.to, "data": request.data, "block": request.block },
+                    "response": response,
+                })
+            })
+            .collect();
+        let content =
+            serde_json::to_string_pretty(&records).unwrap_or_else(|err| logging::critical!(err));
+
+        if let Some(dir) = self.cache.parent() {
+            std::fs::create_dir_all(dir).unwrap_or_else(|err| {
+                logging::critical!("Could not create the folder `{:?}`: {}", dir, err)
+            });
+        }
+        std::fs::write(&self.cache, content).unwrap_or_else(|err| {
+            logging::critical!("Could not write the RPC cache `{:?}`: {}", self.cache, err)
+        });
+    }
+
+    /// The recorded response to the request, else the one of the endpoint, which is recorded.
+    /// `None` when the call was never recorded and there is no endpoint to make it.
+    fn eth_call(&mut self, request: EthCallRequest) -> Result<Option<JsonValue>, String> {
+        if let Some(response) = self.responses.get(&request) {
+            return Ok(Some(response.clone()));
+        }
+        let endpoint = match &self.endpoint {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+
+        let call = endpoint.transport.execute(
+            "eth_call",
+            vec![
+                json!({ "to": request.to, "data": request.data }),
+                json!(request.block),
+            ],
+        );
+        let result = endpoint
+            .runtime
+            .block_on(tokio::time::timeout(RPC_TIMEOUT, call))
+            .map_err(|_| format!("{} did not respond in time", endpoint.url))?;
+
+        // Errors other than reverts, like a node that is down, are not recorded.
+        let response = match result {
+            Ok(result) => json!({ "result": result }),
+            Err(web3::Error::Rpc(error)) => {
+                let error =
+                    serde_json::to_value(&error).unwrap_or_else(|err| logging::critical!(err));
+                if !is_revert(&error) {
+                    return Err(format!(
+                        "{} responded with an error: {}",
+                        endpoint.url, error
+                    ));
+                }
+                json!({ "error": error })
+            }
+            Err(err) => return Err(format!("{} could not be called: {}", endpoint.url, err)),
+        };
+
+        self.responses.insert(request, response.clone());
+        self.save();
+        Ok(Some(response))
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Makes a contract call that isn't mocked through the JSON-RPC endpoint or its cache.
+    /// Returns `None` when calls aren't forwarded or the call can't be answered.
+    pub(crate) fn forwarded_call(
+        &self,
+        contract_address: &Address,
+        fn_name: &str,
+        fn_signature: &str,
+        args: &[Token],
+    ) -> Option<Result<CallOutcome, String>> {
+        crate::ETH_RPC.with(|rpc| {
+            let mut rpc = rpc.borrow_mut();
+            let rpc = rpc.as_mut()?;
+
+            let mut data = ethabi::short_signature(fn_name, &input_types(fn_signature)).to_vec();
+            data.extend(ethabi::encode(args));
+            let request = EthCallRequest {
+                to: format!("{:?}", contract_address),
+                data: format!("0x{}", to_hex(&data)),
+                block: rpc.block.clone(),
+            };
+
+            let response = match rpc.eth_call(request) {
+                Ok(response) => response?,
+                Err(err) => return Some(Err(err)),
+            };
+            if let Some(error) = response.get("error") {
+                return Some(Ok(CallOutcome::Reverted(revert_data(error))));
+            }
+
+            let result = response["result"].as_str().unwrap_or_default();
+            Some(
+                from_hex(result)
+                    .and_then(|bytes| {
+                        ethabi::decode(&return_types(fn_signature), &bytes)
+                            .map_err(|err| err.to_string())
+                    })
+                    .map(CallOutcome::Returned)
+                    .map_err(|err| {
+                        format!(
+                            "Could not decode the result `{}` of `{}`: {}",
+                            result, fn_signature, err
+                        )
+                    }),
+            )
+        })
+    }
+}
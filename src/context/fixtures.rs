@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use graph::{blockchain::Blockchain, data::graphql::ext::DirectiveFinder, data::store::Value};
+use graph_graphql::graphql_parser::schema;
+use serde_json::Value as JsonValue;
+
+use super::{entity_schema, MatchstickInstanceContext};
+use crate::logging;
+
+/// Reads a fixture file. Files ending in `.json` are parsed as JSON, everything else as YAML.
+/// A fixture maps entity types to entities by id:
+/// ```yaml
+/// Gravatar:
+///   "0x1":
+///     owner: "0x89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7"
+///     displayName: First Gravatar
+///     imageUrl: https://example.com/1.png
+/// ```
+pub(crate) fn read_fixture(path: &Path) -> JsonValue {
+    let content = std::fs::read_to_string(path).unwrap_or_else(|err| {
+        logging::critical!(
+            "Something went wrong while trying to read `{:?}`: {}",
+            path,
+            err,
+        )
+    });
+
+    let parsed = if path.extension().map_or(false, |ext| ext == "json") {
+        serde_json::from_str(&content).map_err(|err| err.to_string())
+    } else {
+        serde_yaml::from_str(&content).map_err(|err| err.to_string())
+    };
+
+    parsed.unwrap_or_else(|err| {
+        logging::critical!("Fixture `{:?}` could not be parsed: {}", path, err)
+    })
+}
+
+/// Converts the entities of a fixture to store entities, validating them against `schema.graphql`.
+pub(crate) fn parse_fixture(
+    path: &Path,
+) -> HashMap<String, HashMap<String, HashMap<String, Value>>> {
+    let fixture = read_fixture(path);
+    let entity_types = fixture.as_object().unwrap_or_else(|| {
+        logging::critical!(
+            "Fixture `{:?}` should map entity types to entities by id.",
+            path
+        )
+    });
+
+    let mut errors: Vec<String> = vec![];
+    let mut store = HashMap::new();
+
+    for (entity_type, entities) in entity_types {
+        let entity_definition = match entity_schema::entity_definition(entity_type) {
+            Some(definition) => definition,
+            None => {
+                errors.push(format!(
+                    "No entity type '{}' in the GraphQL schema.",
+                    entity_type
+                ));
+                continue;
+            }
+        };
+
+        let entities = match entities.as_object() {
+            Some(entities) => entities,
+            None => {
+                errors.push(format!(
+                    "'{}' should map entity ids to entities.",
+                    entity_type
+                ));
+                continue;
+            }
+        };
+
+        let mut entity_type_store = HashMap::new();
+        for (id, fields) in entities {
+            match parse_entity(entity_definition, id, fields) {
+                Ok(entity) => {
+                    entity_type_store.insert(entity_schema::id_key(entity_type, id), entity);
+                }
+                Err(mut entity_errors) => errors.append(&mut entity_errors),
+            }
+        }
+
+        store.insert(entity_type.clone(), entity_type_store);
+    }
+
+    if !errors.is_empty() {
+        logging::critical!(
+            "Fixture `{:?}` does not match the GraphQL schema:\n{}",
+            path,
+            errors.join("\n")
+        );
+    }
+
+    store
+}
+
+fn parse_entity(
+    entity_definition: &schema::ObjectType<'static, String>,
+    id: &str,
+    fields: &JsonValue,
+) -> Result<HashMap<String, Value>, Vec<String>> {
+    let entity_type = &entity_definition.name;
+    let fields = fields.as_object().ok_or_else(|| {
+        vec![format!(
+            "{}[{}] should be a map of fields.",
+            entity_type, id
+        )]
+    })?;
+
+    let mut errors = vec![];
+    let mut entity = HashMap::new();
+
+    for (field_name, json) in fields {
+        let field = match entity_definition
+            .fields
+            .iter()
+            .find(|f| &f.name == field_name)
+        {
+            Some(field) => field,
+            None => {
+                errors.push(format!(
+                    "{}[{}] has no field '{}'.",
+                    entity_type, id, field_name
+                ));
+                continue;
+            }
+        };
+
+        if field.is_derived() {
+            errors.push(format!(
+                "{}[{}].{} is derived and can not be set.",
+                entity_type, id, field_name
+            ));
+            continue;
+        }
+
+        match entity_schema::value_from_json(&field.field_type, json) {
+            Ok(value) => {
+                entity.insert(field_name.clone(), value);
+            }
+            Err(err) => errors.push(format!("{}[{}].{}: {}", entity_type, id, field_name, err)),
+        }
+    }
+
+    // The id is taken from the key when the entity doesn't repeat it.
+    if !entity.contains_key("id") {
+        entity.insert("id".to_owned(), entity_schema::id_value(entity_type, id));
+    }
+
+    for f in entity_definition
+        .fields
+        .iter()
+        .filter(|&f| matches!(f.field_type, schema::Type::NonNullType(..)) && !f.is_derived())
+    {
+        if !entity.contains_key(&f.name) {
+            logging::warning!(
+                "Missing a required field '{}' for an entity of type '{}'.",
+                f.name,
+                entity_type,
+            );
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(entity)
+    } else {
+        Err(errors)
+    }
+}
+
+/// Converts the store to the fixture format, with entity types, ids and fields sorted.
+pub(crate) fn store_to_json(
+    store: &HashMap<String, HashMap<String, HashMap<String, Value>>>,
+) -> BTreeMap<String, BTreeMap<String, BTreeMap<String, JsonValue>>> {
+    store
+        .iter()
+        .filter(|(_, entities)| !entities.is_empty())
+        .map(|(entity_type, entities)| {
+            let entities = entities
+                .iter()
+                .map(|(id, entity)| {
+                    let fields = entity
+                        .iter()
+                        .map(|(field, value)| (field.clone(), entity_schema::value_to_json(value)))
+                        .collect();
+                    (id.clone(), fields)
+                })
+                .collect();
+            (entity_type.clone(), entities)
+        })
+        .collect()
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Serialises the store to pretty JSON, which can be loaded back as a fixture.
+    pub(crate) fn dump_store(&self) -> String {
+        serde_json::to_string_pretty(&store_to_json(&self.entities()))
+            .unwrap_or_else(|err| logging::critical!(err))
+    }
+
+    /// Adds the entities of a fixture to the store, replacing entities with the same id.
+    pub(crate) fn insert_fixture(&mut self, path: &Path) {
+        for (entity_type, entities) in parse_fixture(path) {
+            self.entities()
+                .entry(entity_type)
+                .or_insert_with(HashMap::new)
+                .extend(entities);
+        }
+    }
+}
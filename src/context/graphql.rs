@@ -0,0 +1,358 @@
+use std::collections::HashMap;
+
+use graph::{blockchain::Blockchain, data::store::Value};
+use graph_graphql::{
+    graphql_parser::{query as q, schema},
+    prelude::api_schema,
+};
+use lazy_static::lazy_static;
+use serde_json::{json, Map, Value as JsonValue};
+
+use super::{entity_schema, query, MatchstickInstanceContext, SCHEMA};
+use crate::logging;
+
+/// Collections return at most this many entities unless `first` says otherwise, just like in graph-node.
+const DEFAULT_FIRST: usize = 100;
+
+lazy_static! {
+    /// The API schema graph-node generates for `schema.graphql`, whose `Query` type has the
+    /// root fields of a deployed subgraph.
+    static ref API_SCHEMA: schema::Document<'static, String> = api_schema(&SCHEMA)
+        .unwrap_or_else(|err| {
+            logging::critical!(
+                "Something went wrong when trying to generate the API schema of `schema.graphql`: {}",
+                err
+            )
+        });
+}
+
+/// Finds the entity type or interface queried by a root field of the generated API schema,
+/// `gravatar(id: ...)` for a single `Gravatar` or `gravatars(...)` for a collection of them.
+/// Returns the type name and whether the field is a collection.
+fn root_type(field_name: &str) -> Option<(String, bool)> {
+    let query_type = API_SCHEMA.definitions.iter().find_map(|def| match def {
+        schema::Definition::TypeDefinition(schema::TypeDefinition::Object(o))
+            if o.name == "Query" =>
+        {
+            Some(o)
+        }
+        _ => None,
+    })?;
+    let field = query_type.fields.iter().find(|f| f.name == field_name)?;
+
+    // Fields that don't query entities, like `_meta`, aren't supported.
+    let type_name = entity_schema::base_type_name(&field.field_type);
+    entity_schema::type_fields(&type_name)
+        .map(|_| (type_name, entity_schema::is_list_type(&field.field_type)))
+}
+
+/// Converts an argument of the query to JSON, replacing variables with their values.
+fn argument_to_json(
+    value: &q::Value<'_, String>,
+    variables: &Map<String, JsonValue>,
+) -> Result<JsonValue, String> {
+    Ok(match value {
+        q::Value::Variable(name) => variables
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Variable '${}' is not defined.", name))?,
+        q::Value::Int(n) => n
+            .as_i64()
+            .map(JsonValue::from)
+            .ok_or_else(|| format!("Number `{:?}` is too big.", n))?,
+        q::Value::Float(f) => serde_json::Number::from_f64(*f)
+            .map(JsonValue::Number)
+            .ok_or_else(|| format!("`{}` is not a valid number.", f))?,
+        q::Value::String(s) | q::Value::Enum(s) => JsonValue::String(s.clone()),
+        q::Value::Boolean(b) => JsonValue::Bool(*b),
+        q::Value::Null => JsonValue::Null,
+        q::Value::List(items) => JsonValue::Array(
+            items
+                .iter()
+                .map(|item| argument_to_json(item, variables))
+                .collect::<Result<_, _>>()?,
+        ),
+        q::Value::Object(fields) => JsonValue::Object(
+            fields
+                .iter()
+                .map(|(name, value)| Ok((name.clone(), argument_to_json(value, variables)?)))
+                .collect::<Result<_, String>>()?,
+        ),
+    })
+}
+
+/// Whether a fragment with the type condition applies to an entity of the passed type.
+fn applies_to(condition: &q::TypeCondition<'_, String>, entity_type: &str) -> bool {
+    let q::TypeCondition::On(type_name) = condition;
+    type_name == entity_type
+        || entity_schema::concrete_types(type_name)
+            .iter()
+            .any(|t| t == entity_type)
+}
+
+struct Execution<'a, 'c, C: Blockchain> {
+    context: &'c MatchstickInstanceContext<C>,
+    fragments: HashMap<&'a str, &'a q::FragmentDefinition<'a, String>>,
+    variables: Map<String, JsonValue>,
+}
+
+impl<'a, 'c, C: Blockchain> Execution<'a, 'c, C> {
+    /// Flattens fragments into the list of fields selected for an entity of the passed type.
+    fn collect_fields(
+        &self,
+        selection_set: &'a q::SelectionSet<'a, String>,
+        entity_type: &str,
+        fields: &mut Vec<&'a q::Field<'a, String>>,
+    ) -> Result<(), String> {
+        for selection in &selection_set.items {
+            match selection {
+                q::Selection::Field(field) => fields.push(field),
+                q::Selection::FragmentSpread(spread) => {
+                    let fragment = self
+                        .fragments
+                        .get(spread.fragment_name.as_str())
+                        .ok_or_else(|| format!("Unknown fragment '{}'.", spread.fragment_name))?;
+                    if applies_to(&fragment.type_condition, entity_type) {
+                        self.collect_fields(&fragment.selection_set, entity_type, fields)?;
+                    }
+                }
+                q::Selection::InlineFragment(fragment) => {
+                    if fragment
+                        .type_condition
+                        .as_ref()
+                        .map_or(true, |condition| applies_to(condition, entity_type))
+                    {
+                        self.collect_fields(&fragment.selection_set, entity_type, fields)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn arguments(&self, field: &q::Field<'a, String>) -> Result<JsonValue, String> {
+        field
+            .arguments
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), argument_to_json(value, &self.variables)?)))
+            .collect::<Result<Map<String, JsonValue>, String>>()
+            .map(JsonValue::Object)
+    }
+
+    /// Finds an entity by id among the entity types stored under the passed type.
+    fn find_entity(&self, type_name: &str, id: &str) -> Option<(String, HashMap<String, Value>)> {
+        entity_schema::concrete_types(type_name)
+            .into_iter()
+            .find_map(|entity_type| {
+                let entity = self.context.entities().get(&entity_type)?.get(id)?.clone();
+                let entity = self.context.with_derived_fields(&entity_type, id, &entity);
+                Some((entity_type, entity))
+            })
+    }
+
+    fn resolve_collection(
+        &self,
+        type_name: &str,
+        field: &'a q::Field<'a, String>,
+        ids: Option<&[String]>,
+    ) -> Result<JsonValue, String> {
+        let query = query::parse_query(type_name, &self.arguments(field)?)?
+            .with_default_first(DEFAULT_FIRST);
+
+        self.context
+            .query_store(type_name, &query, ids)
+            .iter()
+            .map(|(entity_type, _, entity)| {
+                self.resolve_entity(entity_type, entity, &field.selection_set)
+            })
+            .collect::<Result<Vec<JsonValue>, String>>()
+            .map(JsonValue::Array)
+    }
+
+    fn resolve_entity(
+        &self,
+        entity_type: &str,
+        entity: &HashMap<String, Value>,
+        selection_set: &'a q::SelectionSet<'a, String>,
+    ) -> Result<JsonValue, String> {
+        let mut fields = vec![];
+        self.collect_fields(selection_set, entity_type, &mut fields)?;
+
+        let mut result = Map::new();
+        for field in fields {
+            let key = field.alias.as_ref().unwrap_or(&field.name).clone();
+            if field.name == "__typename" {
+                result.insert(key, JsonValue::String(entity_type.to_owned()));
+                continue;
+            }
+
+            let definition = entity_schema::field_definition(entity_type, &field.name)
+                .ok_or_else(|| format!("Type '{}' has no field '{}'.", entity_type, field.name))?;
+            let field_type = entity_schema::base_type_name(&definition.field_type);
+            let value = entity.get(&field.name).unwrap_or(&Value::Null);
+
+            let value = if entity_schema::type_fields(&field_type).is_none() {
+                if !field.selection_set.items.is_empty() {
+                    return Err(format!(
+                        "Field '{}' of type '{}' has no subfields.",
+                        field.name, field_type
+                    ));
+                }
+                entity_schema::value_to_json(value)
+            } else if field.selection_set.items.is_empty() {
+                return Err(format!(
+                    "Field '{}' of type '{}' must have a selection of subfields.",
+                    field.name, field_type
+                ));
+            } else if entity_schema::is_list_type(&definition.field_type) {
+                let ids: Vec<String> = match value {
+                    Value::List(values) => values
+                        .iter()
+                        .filter_map(entity_schema::reference_key)
+                        .collect(),
+                    _ => vec![],
+                };
+                self.resolve_collection(&field_type, field, Some(&ids))?
+            } else {
+                match entity_schema::reference_key(value)
+                    .and_then(|id| self.find_entity(&field_type, &id))
+                {
+                    Some((entity_type, entity)) => {
+                        self.resolve_entity(&entity_type, &entity, &field.selection_set)?
+                    }
+                    None => JsonValue::Null,
+                }
+            };
+
+            result.insert(key, value);
+        }
+
+        Ok(JsonValue::Object(result))
+    }
+
+    fn resolve_root(
+        &self,
+        selection_set: &'a q::SelectionSet<'a, String>,
+    ) -> Result<JsonValue, String> {
+        let mut fields = vec![];
+        self.collect_fields(selection_set, "Query", &mut fields)?;
+
+        let mut result = Map::new();
+        for field in fields {
+            let key = field.alias.as_ref().unwrap_or(&field.name).clone();
+            if field.name == "__typename" {
+                result.insert(key, JsonValue::String("Query".to_owned()));
+                continue;
+            }
+
+            let (type_name, is_collection) = root_type(&field.name)
+                .ok_or_else(|| format!("Type 'Query' has no field '{}'.", field.name))?;
+
+            let value = if is_collection {
+                self.resolve_collection(&type_name, field, None)?
+            } else {
+                let arguments = self.arguments(field)?;
+                let id = match arguments.get("id") {
+                    Some(JsonValue::String(id)) => id.clone(),
+                    Some(JsonValue::Number(id)) => id.to_string(),
+                    _ => {
+                        return Err(format!(
+                            "Field '{}' needs the `id` of the entity.",
+                            field.name
+                        ))
+                    }
+                };
+                if let Some(name) = arguments
+                    .as_object()
+                    .and_then(|args| args.keys().find(|name| *name != "id"))
+                {
+                    return Err(format!(
+                        "Unknown argument '{}' on field '{}'.",
+                        name, field.name
+                    ));
+                }
+
+                match self.find_entity(&type_name, &entity_schema::id_key(&type_name, &id)) {
+                    Some((entity_type, entity)) => {
+                        self.resolve_entity(&entity_type, &entity, &field.selection_set)?
+                    }
+                    None => JsonValue::Null,
+                }
+            };
+
+            result.insert(key, value);
+        }
+
+        Ok(JsonValue::Object(result))
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Executes a GraphQL query against the store the way the GraphQL API generated for the
+    /// subgraph would, with filters, ordering, pagination, nested and derived fields.
+    /// Returns the response as `{"data": ...}`, or as `{"errors": [...]}` when the query is invalid.
+    pub(crate) fn execute_graphql(&self, query: &str, variables: &JsonValue) -> JsonValue {
+        match self.try_execute_graphql(query, variables) {
+            Ok(data) => json!({ "data": data }),
+            Err(err) => json!({ "errors": [{ "message": err }] }),
+        }
+    }
+
+    fn try_execute_graphql(&self, query: &str, variables: &JsonValue) -> Result<JsonValue, String> {
+        let document = q::parse_query::<String>(query).map_err(|err| err.to_string())?;
+
+        let fragments = document
+            .definitions
+            .iter()
+            .filter_map(|def| match def {
+                q::Definition::Fragment(fragment) => Some((fragment.name.as_str(), fragment)),
+                _ => None,
+            })
+            .collect();
+
+        let (selection_set, variable_definitions) = document
+            .definitions
+            .iter()
+            .find_map(|def| match def {
+                q::Definition::Operation(q::OperationDefinition::SelectionSet(s)) => {
+                    Some(Ok((s, &[][..])))
+                }
+                q::Definition::Operation(q::OperationDefinition::Query(query)) => Some(Ok((
+                    &query.selection_set,
+                    query.variable_definitions.as_slice(),
+                ))),
+                q::Definition::Operation(_) => Some(Err("Only queries are supported.".to_owned())),
+                q::Definition::Fragment(_) => None,
+            })
+            .ok_or_else(|| "The document has no query.".to_owned())??;
+
+        let mut provided = match variables {
+            JsonValue::Object(variables) => variables.clone(),
+            JsonValue::Null => Map::new(),
+            _ => {
+                return Err(format!(
+                    "Variables should be an object, but got `{}`",
+                    variables
+                ))
+            }
+        };
+        for definition in variable_definitions {
+            if !provided.contains_key(&definition.name) {
+                if let Some(default) = &definition.default_value {
+                    provided.insert(
+                        definition.name.clone(),
+                        argument_to_json(default, &Map::new())?,
+                    );
+                }
+            }
+        }
+
+        let execution = Execution {
+            context: self,
+            fragments,
+            variables: provided,
+        };
+        execution.resolve_root(selection_set)
+    }
+}
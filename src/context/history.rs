@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use graph::{blockchain::Blockchain, data::store::Value};
+
+use super::MatchstickInstanceContext;
+
+/// The block and event the mappings are currently handling, set with `mockBlock`.
+/// Every change to the store is recorded with it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct MockBlock {
+    pub(crate) number: u32,
+    pub(crate) event: Option<String>,
+}
+
+/// A single `store.set` or `store.remove` of an entity. `previous` and `current` hold
+/// the entity before and after the change, `None` meaning it wasn't in the store.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct EntityChange {
+    pub(crate) block: MockBlock,
+    pub(crate) entity_type: String,
+    pub(crate) id: String,
+    pub(crate) previous: Option<HashMap<String, Value>>,
+    pub(crate) current: Option<HashMap<String, Value>>,
+}
+
+/// A data source created from a template with `dataSource.create`, at the block it was created in.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CreatedDataSource {
+    pub(crate) name: String,
+    pub(crate) params: Vec<String>,
+    pub(crate) block: MockBlock,
+}
+
+impl EntityChange {
+    fn describe(&self) -> String {
+        let operation = match &self.current {
+            Some(entity) => {
+                let mut fields: Vec<String> = entity
+                    .iter()
+                    .map(|(field, value)| format!("{}: {}", field, value))
+                    .collect();
+                fields.sort();
+                format!("set {{ {} }}", fields.join(", "))
+            }
+            None => "remove".to_owned(),
+        };
+
+        match &self.block.event {
+            Some(event) => format!("block {} ({}): {}", self.block.number, event, operation),
+            None => format!("block {}: {}", self.block.number, operation),
+        }
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// All recorded changes to one entity, oldest first.
+    pub(crate) fn entity_history(&self, entity_type: &str, id: &str) -> Vec<EntityChange> {
+        self.flush_cached_changes();
+        self.store
+            .history()
+            .iter()
+            .filter(|change| change.entity_type == entity_type && change.id == id)
+            .cloned()
+            .collect()
+    }
+
+    /// The entity as it was at the end of the passed block. Entities that were put in
+    /// the store without `store.set`, like fixtures, are considered to always have been there.
+    pub(crate) fn entity_at_block(
+        &self,
+        entity_type: &str,
+        id: &str,
+        block_number: u32,
+    ) -> Option<HashMap<String, Value>> {
+        let history = self.entity_history(entity_type, id);
+
+        match history
+            .iter()
+            .rev()
+            .find(|change| change.block.number <= block_number)
+        {
+            Some(change) => change.current.clone(),
+            None => match history.first() {
+                Some(change) => change.previous.clone(),
+                None => self
+                    .entities()
+                    .get(entity_type)
+                    .and_then(|entities| entities.get(id))
+                    .cloned(),
+            },
+        }
+    }
+
+    /// Reverts the store to the end of the passed block, like graph-node does on a chain
+    /// reorganisation. Every change and data source recorded at a later block is undone,
+    /// whatever order the blocks were mocked in, and the passed block becomes the current one.
+    pub(crate) fn revert_store_to_block(&mut self, block_number: u32) {
+        // Pending changes are recorded at the current block first, so they are only
+        // undone when that block is reverted too.
+        self.flush_cached_changes();
+        self.store.revert_to_block(block_number);
+
+        self.data_sources
+            .retain(|data_source| data_source.block.number <= block_number);
+        self.block = MockBlock {
+            number: block_number,
+            event: None,
+        };
+    }
+
+    /// Describes the changes to an entity, one line per change.
+    pub(crate) fn describe_history(&self, entity_type: &str, id: &str) -> String {
+        let history = self.entity_history(entity_type, id);
+        if history.is_empty() {
+            return "  (no changes)".to_owned();
+        }
+
+        history
+            .iter()
+            .map(|change| format!("  {}", change.describe()))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
@@ -0,0 +1,496 @@
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::str::FromStr;
+
+use graph::{
+    blockchain::Blockchain,
+    prelude::{
+        ethabi::{
+            token::{LenientTokenizer, Tokenizer},
+            Address, ParamType, Token, Uint,
+        },
+        BigInt,
+    },
+};
+
+use super::{collect_types, get_kind, reverts::CallOutcome, MatchstickInstanceContext};
+
+/// What a mocked contract call accepts for one of its arguments. Matchers are written as strings:
+/// - `*` matches any value,
+/// - `10..20`, `10..`, `..20`, `>10`, `>=10`, `<20` and `<=20` match integers in a range, bounds of `..` included,
+/// - `0xab*` matches addresses and bytes starting with `0xab`, and `ab*` strings starting with `ab`,
+/// - anything else matches that exact value, written like in `ethereum.Value` (`42`, `true`, `0x8920…`).
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum ArgMatcher {
+    Any,
+    Equals(Token),
+    Range {
+        /// Signed integers are compared by their two's complement with the sign bit flipped.
+        signed: bool,
+        start: Bound<Uint>,
+        end: Bound<Uint>,
+    },
+    Prefix(String),
+}
+
+fn is_hex_type(param_type: &ParamType) -> bool {
+    matches!(
+        param_type,
+        ParamType::Address | ParamType::Bytes | ParamType::FixedBytes(_)
+    )
+}
+
+pub(crate) fn tokenize(param_type: &ParamType, text: &str) -> Result<Token, String> {
+    let text = text.trim();
+    let text = if is_hex_type(param_type) {
+        text.strip_prefix("0x").unwrap_or(text)
+    } else {
+        text
+    };
+
+    LenientTokenizer::tokenize(param_type, text)
+        .map_err(|err| format!("`{}` is not a valid `{}`: {}", text, param_type, err))
+}
+
+/// The key integers are ordered by, so signed and unsigned integers can both be compared as `Uint`.
+fn order_key(value: Uint, signed: bool) -> Uint {
+    if signed {
+        value ^ (Uint::one() << 255)
+    } else {
+        value
+    }
+}
+
+fn integer_bound(
+    param_type: &ParamType,
+    text: &str,
+    inclusive: bool,
+) -> Result<Bound<Uint>, String> {
+    if text.trim().is_empty() {
+        return Ok(Bound::Unbounded);
+    }
+
+    let value = match tokenize(param_type, text)? {
+        Token::Int(value) => order_key(value, true),
+        Token::Uint(value) => order_key(value, false),
+        token => return Err(format!("`{}` is not an integer", token)),
+    };
+    Ok(if inclusive {
+        Bound::Included(value)
+    } else {
+        Bound::Excluded(value)
+    })
+}
+
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// The token written as hex or as a string, the way `Prefix` matchers compare it.
+fn token_text(token: &Token) -> Option<String> {
+    match token {
+        Token::Address(address) => Some(to_hex(address.as_bytes())),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => Some(to_hex(bytes)),
+        Token::String(string) => Some(string.clone()),
+        _ => None,
+    }
+}
+
+/// Reads the matcher of an argument of the passed type, see `ArgMatcher`.
+pub(crate) fn parse_arg_matcher(param_type: &ParamType, text: &str) -> Result<ArgMatcher, String> {
+    let text = text.trim();
+    if text == "*" {
+        return Ok(ArgMatcher::Any);
+    }
+
+    match param_type {
+        ParamType::Int(_) | ParamType::Uint(_) => {
+            let signed = matches!(param_type, ParamType::Int(_));
+            let range = if let Some((start, end)) = text.split_once("..") {
+                Some((
+                    integer_bound(param_type, start, true)?,
+                    integer_bound(param_type, end, true)?,
+                ))
+            } else if let Some(start) = text.strip_prefix(">=") {
+                Some((integer_bound(param_type, start, true)?, Bound::Unbounded))
+            } else if let Some(start) = text.strip_prefix('>') {
+                Some((integer_bound(param_type, start, false)?, Bound::Unbounded))
+            } else if let Some(end) = text.strip_prefix("<=") {
+                Some((Bound::Unbounded, integer_bound(param_type, end, true)?))
+            } else if let Some(end) = text.strip_prefix('<') {
+                Some((Bound::Unbounded, integer_bound(param_type, end, false)?))
+            } else {
+                None
+            };
+
+            if let Some((start, end)) = range {
+                return Ok(ArgMatcher::Range { signed, start, end });
+            }
+        }
+        ParamType::String => {
+            if let Some(prefix) = text.strip_suffix('*') {
+                return Ok(ArgMatcher::Prefix(prefix.to_owned()));
+            }
+        }
+        param_type if is_hex_type(param_type) => {
+            if let Some(prefix) = text.strip_suffix('*') {
+                let prefix = prefix.strip_prefix("0x").unwrap_or(prefix);
+                return Ok(ArgMatcher::Prefix(prefix.to_lowercase()));
+            }
+        }
+        _ => {}
+    }
+
+    tokenize(param_type, text).map(ArgMatcher::Equals)
+}
+
+/// Undoes `order_key`, writing the integer in decimal.
+fn format_bound(value: &Uint, signed: bool, prefix: &str) -> String {
+    if signed {
+        format!(
+            "{}{}",
+            prefix,
+            BigInt::from_signed_u256(&order_key(*value, true))
+        )
+    } else {
+        format!("{}{}", prefix, value)
+    }
+}
+
+impl fmt::Display for ArgMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgMatcher::Any => write!(f, "*"),
+            ArgMatcher::Equals(token) => write!(f, "{}", format_token(token)),
+            ArgMatcher::Range { signed, start, end } => {
+                let bounds: Vec<String> = [
+                    match start {
+                        Bound::Included(value) => Some(format_bound(value, *signed, ">=")),
+                        Bound::Excluded(value) => Some(format_bound(value, *signed, ">")),
+                        Bound::Unbounded => None,
+                    },
+                    match end {
+                        Bound::Included(value) => Some(format_bound(value, *signed, "<=")),
+                        Bound::Excluded(value) => Some(format_bound(value, *signed, "<")),
+                        Bound::Unbounded => None,
+                    },
+                ]
+                .into_iter()
+                .flatten()
+                .collect();
+                write!(f, "{}", bounds.join(" and "))
+            }
+            ArgMatcher::Prefix(prefix) => write!(f, "{}*", prefix),
+        }
+    }
+}
+
+impl ArgMatcher {
+    pub(crate) fn matches(&self, token: &Token) -> bool {
+        match self {
+            ArgMatcher::Any => true,
+            ArgMatcher::Equals(expected) => expected == token,
+            ArgMatcher::Range { signed, start, end } => match token {
+                Token::Int(value) | Token::Uint(value) => {
+                    (*start, *end).contains(&order_key(*value, *signed))
+                }
+                _ => false,
+            },
+            ArgMatcher::Prefix(prefix) => {
+                token_text(token).map_or(false, |text| text.starts_with(prefix.as_str()))
+            }
+        }
+    }
+}
+
+/// Describes the contract calls a mock or an assertion applies to.
+/// Without a contract address, calls to any contract match.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CallPattern {
+    pub(crate) contract_address: Option<Address>,
+    pub(crate) fn_name: String,
+    pub(crate) fn_signature: String,
+    pub(crate) args: Vec<ArgMatcher>,
+}
+
+/// Identifies a mock, to keep track of the mocks calls were served from.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum MockId {
+    /// A mock by the exact arguments of the call, keyed by the id of the call.
+    Function(String),
+    /// A `mockFunctionMatching` mock, by its index in `call_matchers`.
+    Matcher(usize),
+}
+
+/// A mocked contract call that can match many calls, created with `mockFunctionMatching`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct CallMatcher {
+    pub(crate) pattern: CallPattern,
+    pub(crate) outcome: CallOutcome,
+}
+
+/// Reads a call pattern, with `*` as the contract address matching any contract and an
+/// argument matcher, see `ArgMatcher`, for every argument of the function signature.
+pub(crate) fn parse_call_pattern(
+    contract_address: &str,
+    fn_name: String,
+    fn_signature: String,
+    arg_matchers: &[String],
+) -> Result<CallPattern, String> {
+    let contract_address = match contract_address.trim() {
+        "*" => None,
+        address => Some(
+            Address::from_str(address.trim_start_matches("0x"))
+                .map_err(|err| format!("`{}` is not a valid contract address: {}", address, err))?,
+        ),
+    };
+
+    let tmp_str = fn_signature.replace(&(fn_name.clone() + "("), "");
+    let components: Vec<&str> = tmp_str.split("):").collect();
+    let arg_types: Vec<String> = collect_types(components[0]);
+
+    if arg_types.len() != arg_matchers.len() {
+        return Err(format!(
+            "{} expected {} argument matchers, but received {}",
+            fn_name,
+            arg_types.len(),
+            arg_matchers.len()
+        ));
+    }
+
+    let args = arg_types
+        .iter()
+        .zip(arg_matchers.iter())
+        .enumerate()
+        .map(|(index, (arg_type, matcher))| {
+            parse_arg_matcher(&get_kind(arg_type.to_owned()), matcher).map_err(|err| {
+                format!(
+                    "`{}` invalid matcher at position {}: {}",
+                    fn_name,
+                    index + 1,
+                    err
+                )
+            })
+        })
+        .collect::<Result<Vec<ArgMatcher>, String>>()?;
+
+    Ok(CallPattern {
+        contract_address,
+        fn_name,
+        fn_signature,
+        args,
+    })
+}
+
+impl CallPattern {
+    pub(crate) fn describe(&self) -> String {
+        let args: Vec<String> = self.args.iter().map(|arg| arg.to_string()).collect();
+        format!(
+            "{}.{} with [{}]",
+            self.contract_address
+                .map_or_else(|| "*".to_owned(), |address| format!("{:?}", address)),
+            self.fn_signature,
+            args.join(", ")
+        )
+    }
+
+    fn matches(
+        &self,
+        contract_address: &Address,
+        fn_name: &str,
+        fn_signature: &str,
+        args: &[Token],
+    ) -> bool {
+        self.contract_address
+            .map_or(true, |address| address == *contract_address)
+            && self.fn_name == fn_name
+            && self.fn_signature == fn_signature
+            && self.args.len() == args.len()
+            && self
+                .args
+                .iter()
+                .zip(args)
+                .all(|(matcher, arg)| matcher.matches(arg))
+    }
+
+    /// How specific the pattern is: first the number of exact values, the contract address included,
+    /// then the number of arguments matched by a range or a prefix.
+    fn specificity(&self) -> (usize, usize) {
+        let exact = self
+            .args
+            .iter()
+            .filter(|matcher| matches!(matcher, ArgMatcher::Equals(_)))
+            .count()
+            + self.contract_address.map_or(0, |_| 1);
+        let predicates = self
+            .args
+            .iter()
+            .filter(|matcher| matches!(matcher, ArgMatcher::Range { .. } | ArgMatcher::Prefix(_)))
+            .count();
+        (exact, predicates)
+    }
+}
+
+/// A contract call made through `ethereum.call` while running a test, with how it ended.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct RecordedCall {
+    pub(crate) contract_address: Address,
+    pub(crate) fn_name: String,
+    pub(crate) fn_signature: String,
+    pub(crate) args: Vec<Token>,
+    pub(crate) result: CallOutcome,
+    /// Whether the result came from a mock or the JSON-RPC endpoint,
+    /// rather than from the policy for unmocked calls.
+    pub(crate) mocked: bool,
+    /// Whether an `assert.contractCalled` has accounted for the call.
+    pub(crate) verified: bool,
+}
+
+/// The contract, function and arguments of a call mocked by its exact arguments.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct MockedFunction {
+    pub(crate) contract_address: Address,
+    pub(crate) fn_name: String,
+    pub(crate) fn_signature: String,
+    pub(crate) args: Vec<Token>,
+}
+
+/// Writes a token the way it reads in a test: integers in decimal,
+/// addresses and bytes in hex and strings quoted.
+pub(crate) fn format_token(token: &Token) -> String {
+    match token {
+        Token::Address(address) => format!("{:?}", address),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => format!("0x{}", to_hex(bytes)),
+        Token::Int(value) => BigInt::from_signed_u256(value).to_string(),
+        Token::Uint(value) => value.to_string(),
+        Token::Bool(value) => value.to_string(),
+        Token::String(value) => format!("{:?}", value),
+        Token::Array(tokens) | Token::FixedArray(tokens) => {
+            format!("[{}]", describe_tokens(tokens))
+        }
+        Token::Tuple(tokens) => format!("({})", describe_tokens(tokens)),
+    }
+}
+
+pub(crate) fn describe_tokens(tokens: &[Token]) -> String {
+    tokens
+        .iter()
+        .map(format_token)
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl RecordedCall {
+    pub(crate) fn describe(&self) -> String {
+        let result = match &self.result {
+            CallOutcome::Returned(tokens) => format!("[{}]", describe_tokens(tokens)),
+            CallOutcome::Reverted(None) => "reverted".to_owned(),
+            CallOutcome::Reverted(Some(revert)) => format!("reverted with {}", revert),
+            CallOutcome::Failed(message) => format!("failed: {}", message),
+        };
+        format!(
+            "{:?}.{}({}) -> {}",
+            self.contract_address,
+            self.fn_signature,
+            describe_tokens(&self.args),
+            result
+        )
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Finds how a contract call ends: the next outcome queued with `mockFunctionOnce`,
+    /// then the one mocked with `mockFunctionAtBlock` for the current block, then the one of
+    /// `mockFunction` and last the one of the most specific `mockFunctionMatching`.
+    pub(crate) fn mocked_outcome(
+        &mut self,
+        fn_id: &str,
+        contract_address: &Address,
+        fn_name: &str,
+        fn_signature: &str,
+        args: &[Token],
+    ) -> Option<CallOutcome> {
+        let mock_id = MockId::Function(fn_id.to_owned());
+
+        if let Some(outcome) = self
+            .fn_ret_queue
+            .get_mut(fn_id)
+            .and_then(|queue| queue.pop_front())
+        {
+            self.served_mocks.insert(mock_id);
+            return Some(outcome);
+        }
+
+        let block_number = self.block.number;
+        if let Some((_, outcome)) = self
+            .fn_ret_by_block
+            .get(fn_id)
+            .and_then(|outcomes| outcomes.range(..=block_number).next_back())
+        {
+            self.served_mocks.insert(mock_id);
+            return Some(outcome.clone());
+        }
+
+        if let Some(outcome) = self.fn_ret_map.get(fn_id) {
+            self.served_mocks.insert(mock_id);
+            return Some(outcome.clone());
+        }
+
+        let (index, outcome) = self
+            .matching_call(contract_address, fn_name, fn_signature, args)
+            .map(|(index, outcome)| (index, outcome.clone()))?;
+        self.served_mocks.insert(MockId::Matcher(index));
+        Some(outcome)
+    }
+
+    /// Finds the outcome of the most specific matching mock, with its index. Between mocks
+    /// that are just as specific, the one created last wins, like with `mockFunction`.
+    pub(crate) fn matching_call(
+        &self,
+        contract_address: &Address,
+        fn_name: &str,
+        fn_signature: &str,
+        args: &[Token],
+    ) -> Option<(usize, &CallOutcome)> {
+        self.call_matchers
+            .iter()
+            .enumerate()
+            .filter(|(_, matcher)| {
+                matcher
+                    .pattern
+                    .matches(contract_address, fn_name, fn_signature, args)
+            })
+            .max_by_key(|(_, matcher)| matcher.pattern.specificity())
+            .map(|(index, matcher)| (index, &matcher.outcome))
+    }
+
+    /// Describes the recorded calls, one line per call.
+    pub(crate) fn describe_calls(&self) -> String {
+        if self.calls.is_empty() {
+            return "  (no calls)".to_owned();
+        }
+
+        self.calls
+            .iter()
+            .map(|call| format!("  {}", call.describe()))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    /// Counts the recorded calls matching the pattern and marks them as verified.
+    pub(crate) fn verify_calls(&mut self, pattern: &CallPattern) -> usize {
+        let mut count = 0;
+        for call in self.calls.iter_mut().filter(|call| {
+            pattern.matches(
+                &call.contract_address,
+                &call.fn_name,
+                &call.fn_signature,
+                &call.args,
+            )
+        }) {
+            call.verified = true;
+            count += 1;
+        }
+        count
+    }
+}
@@ -0,0 +1,281 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use graph::{blockchain::Blockchain, data::store::Value};
+use graph_graphql::graphql_parser::schema;
+use serde_json::Value as JsonValue;
+
+use super::{entity_schema, values, MatchstickInstanceContext};
+
+/// The comparison a filter makes, taken from the suffix of its key, like in GraphQL queries:
+/// `owner`, `owner_not`, `owner_in`, `owner_not_in`, `count_gt`, `count_gte`, `count_lt` and `count_lte`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Equals,
+    Not,
+    In,
+    NotIn,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+const SUFFIXES: [(&str, Operator); 7] = [
+    ("_not_in", Operator::NotIn),
+    ("_not", Operator::Not),
+    ("_in", Operator::In),
+    ("_gte", Operator::Gte),
+    ("_gt", Operator::Gt),
+    ("_lte", Operator::Lte),
+    ("_lt", Operator::Lt),
+];
+
+#[derive(Debug)]
+struct Filter {
+    field_name: String,
+    operator: Operator,
+    value: Value,
+}
+
+/// A query on the entities of one type in the mock store. It is read from JSON
+/// shaped like the arguments of a GraphQL collection query:
+/// ```json
+/// {
+///     "where": { "displayName_in": ["First Gravatar", "Second Gravatar"] },
+///     "orderBy": "displayName",
+///     "orderDirection": "desc",
+///     "first": 10,
+///     "skip": 0
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub(crate) struct EntityQuery {
+    filters: Vec<Filter>,
+    order_by: Option<String>,
+    descending: bool,
+    first: Option<usize>,
+    skip: usize,
+}
+
+fn field_type(
+    type_name: &str,
+    fields: &'static [schema::Field<'static, String>],
+    field_name: &str,
+) -> Result<&'static schema::Type<'static, String>, String> {
+    fields
+        .iter()
+        .find(|f| f.name == field_name)
+        .map(|f| &f.field_type)
+        .ok_or_else(|| format!("Type '{}' has no field '{}'.", type_name, field_name))
+}
+
+fn parse_filter(
+    type_name: &str,
+    fields: &'static [schema::Field<'static, String>],
+    key: &str,
+    json: &JsonValue,
+) -> Result<Filter, String> {
+    // A field that happens to end in one of the suffixes is still compared for equality.
+    let (field_name, operator) = if field_type(type_name, fields, key).is_ok() {
+        (key, Operator::Equals)
+    } else {
+        SUFFIXES
+            .iter()
+            .find_map(|(suffix, operator)| {
+                key.strip_suffix(suffix)
+                    .map(|field_name| (field_name, *operator))
+            })
+            .unwrap_or((key, Operator::Equals))
+    };
+
+    let field_type = field_type(type_name, fields, field_name)?;
+    let value = match operator {
+        Operator::In | Operator::NotIn => match json {
+            JsonValue::Array(items) => items
+                .iter()
+                .map(|item| entity_schema::value_from_json(field_type, item))
+                .collect::<Result<Vec<Value>, String>>()
+                .map(Value::List),
+            _ => Err(format!("expected a list, but got `{}`", json)),
+        },
+        _ => entity_schema::value_from_json(field_type, json),
+    }
+    .map_err(|err| format!("Filter '{}': {}", key, err))?;
+
+    Ok(Filter {
+        field_name: field_name.to_owned(),
+        operator,
+        value,
+    })
+}
+
+/// Reads the `where` part of a query, mapping filter keys to the values they compare with.
+/// Entities can be queried by their type or by an interface they implement.
+pub(crate) fn parse_filters(type_name: &str, json: &JsonValue) -> Result<EntityQuery, String> {
+    let fields = entity_schema::type_fields(type_name)
+        .ok_or_else(|| format!("No entity type '{}' in the GraphQL schema.", type_name))?;
+    let filters = json
+        .as_object()
+        .ok_or_else(|| format!("Filters should be an object, but got `{}`", json))?
+        .iter()
+        .map(|(key, value)| parse_filter(type_name, fields, key, value))
+        .collect::<Result<Vec<Filter>, String>>()?;
+
+    Ok(EntityQuery {
+        filters,
+        ..Default::default()
+    })
+}
+
+/// Reads a whole query, see `EntityQuery`.
+pub(crate) fn parse_query(type_name: &str, json: &JsonValue) -> Result<EntityQuery, String> {
+    let object = json
+        .as_object()
+        .ok_or_else(|| format!("A query should be an object, but got `{}`", json))?;
+
+    let mut query = match object.get("where") {
+        Some(filters) => parse_filters(type_name, filters)?,
+        None => EntityQuery::default(),
+    };
+
+    for (key, value) in object {
+        match key.as_str() {
+            "where" => {}
+            "orderBy" => {
+                let order_by = value.as_str().ok_or_else(|| {
+                    format!("'orderBy' should be a field name, but got `{}`", value)
+                })?;
+                if let Some(fields) = entity_schema::type_fields(type_name) {
+                    field_type(type_name, fields, order_by)?;
+                }
+                query.order_by = Some(order_by.to_owned());
+            }
+            "orderDirection" => {
+                query.descending = match value.as_str() {
+                    Some("asc") => false,
+                    Some("desc") => true,
+                    _ => {
+                        return Err(format!(
+                            "'orderDirection' should be `asc` or `desc`, but got `{}`",
+                            value
+                        ))
+                    }
+                }
+            }
+            "first" | "skip" => {
+                let number = value
+                    .as_u64()
+                    .ok_or_else(|| format!("'{}' should be a number, but got `{}`", key, value))?
+                    as usize;
+                if key == "first" {
+                    query.first = Some(number);
+                } else {
+                    query.skip = number;
+                }
+            }
+            _ => return Err(format!("Unknown query argument '{}'.", key)),
+        }
+    }
+
+    Ok(query)
+}
+
+impl Filter {
+    fn matches(&self, entity: &HashMap<String, Value>) -> bool {
+        let value = entity.get(&self.field_name).unwrap_or(&Value::Null);
+        let ordering = values::compare_values(value, &self.value);
+
+        match self.operator {
+            Operator::Equals => values::values_equal(value, &self.value),
+            Operator::Not => !values::values_equal(value, &self.value),
+            Operator::In | Operator::NotIn => {
+                let contained = match &self.value {
+                    Value::List(candidates) => {
+                        candidates.iter().any(|c| values::values_equal(value, c))
+                    }
+                    _ => false,
+                };
+                contained == (self.operator == Operator::In)
+            }
+            Operator::Gt => ordering == Some(Ordering::Greater),
+            Operator::Gte => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            Operator::Lt => ordering == Some(Ordering::Less),
+            Operator::Lte => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+        }
+    }
+}
+
+impl EntityQuery {
+    /// Limits the number of results when the query doesn't, like graph-node does for
+    /// collections in GraphQL queries.
+    pub(crate) fn with_default_first(mut self, first: usize) -> Self {
+        self.first.get_or_insert(first);
+        self
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Runs a query against the entities of a type, or of all types implementing an interface,
+    /// with their derived fields resolved. With `ids`, only the entities with those ids are queried.
+    /// Returns the entity type, id and fields of every entity in the result.
+    ///
+    /// Entities are ordered by id, unless the query orders them by another field. Entities
+    /// without a value for that field come after the others, or before them when descending.
+    pub(crate) fn query_store(
+        &self,
+        type_name: &str,
+        query: &EntityQuery,
+        ids: Option<&[String]>,
+    ) -> Vec<(String, String, HashMap<String, Value>)> {
+        let mut entities: Vec<(String, String, HashMap<String, Value>)> =
+            entity_schema::concrete_types(type_name)
+                .into_iter()
+                .flat_map(|entity_type| {
+                    let stored = self.entities().get(&entity_type).cloned();
+                    stored
+                        .into_iter()
+                        .flatten()
+                        .filter(|(id, _)| ids.map_or(true, |ids| ids.contains(id)))
+                        .map(|(id, entity)| {
+                            (
+                                entity_type.clone(),
+                                id.clone(),
+                                self.with_derived_fields(&entity_type, id, entity),
+                            )
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .filter(|(_, _, entity)| query.filters.iter().all(|filter| filter.matches(entity)))
+                .collect();
+
+        entities.sort_by(|(_, left_id, left), (_, right_id, right)| {
+            let ordering = match &query.order_by {
+                Some(field) => match (left.get(field), right.get(field)) {
+                    (Some(l), Some(r))
+                        if !matches!(l, Value::Null) && !matches!(r, Value::Null) =>
+                    {
+                        values::compare_values(l, r).unwrap_or(Ordering::Equal)
+                    }
+                    (Some(l), _) if !matches!(l, Value::Null) => Ordering::Less,
+                    (_, Some(r)) if !matches!(r, Value::Null) => Ordering::Greater,
+                    _ => Ordering::Equal,
+                },
+                None => Ordering::Equal,
+            }
+            .then_with(|| left_id.cmp(right_id));
+
+            if query.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+
+        entities
+            .into_iter()
+            .skip(query.skip)
+            .take(query.first.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use graph::{blockchain::Blockchain, data::graphql::ext::DirectiveFinder, data::store::Value};
+
+use super::{entity_schema, MatchstickInstanceContext};
+
+/// The formats the store can be exported to as a graph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl GraphFormat {
+    pub(crate) fn parse(name: &str) -> Result<Self, String> {
+        match name.to_lowercase().as_str() {
+            "dot" | "graphviz" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            _ => Err(format!(
+                "Unknown graph format '{}', expected `dot` or `mermaid`.",
+                name
+            )),
+        }
+    }
+}
+
+/// An edge from an entity to an entity it references, named after the relation field.
+/// Edges of `@derivedFrom` fields are kept apart, so they can be drawn differently.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Edge {
+    from: (String, String),
+    to: (String, String),
+    field_name: String,
+    derived: bool,
+}
+
+/// The entity type a relation field points to, `None` for fields holding scalars or enums.
+fn relation_type(entity_type: &str, field_name: &str) -> Option<String> {
+    let field = entity_schema::field_definition(entity_type, field_name)?;
+    let type_name = entity_schema::base_type_name(&field.field_type);
+    entity_schema::type_fields(&type_name).map(|_| type_name)
+}
+
+fn is_derived(entity_type: &str, field_name: &str) -> bool {
+    entity_schema::field_definition(entity_type, field_name)
+        .map_or(false, |field| field.is_derived())
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn escape_mermaid(text: &str) -> String {
+    text.replace('"', "#quot;")
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Finds the stored entity a relation value points to. For relations to an interface,
+    /// that can be an entity of any type implementing it. Entities that are not in
+    /// the store are returned with the type of the relation, so broken references show up.
+    fn referenced_entity(&self, type_name: &str, value: &Value) -> Option<(String, String)> {
+        let id = entity_schema::reference_key(value)?;
+        let concrete_types = entity_schema::concrete_types(type_name);
+        let entity_type = concrete_types
+            .iter()
+            .find(|entity_type| {
+                self.entities()
+                    .get(*entity_type)
+                    .map_or(false, |entities| entities.contains_key(&id))
+            })
+            .cloned()
+            .unwrap_or_else(|| type_name.to_owned());
+
+        Some((entity_type, id))
+    }
+
+    fn relation_edges(&self) -> BTreeSet<Edge> {
+        let mut edges = BTreeSet::new();
+        let store = self.entities().clone();
+
+        for (entity_type, entities) in &store {
+            for (id, entity) in entities {
+                for (field_name, value) in self.with_derived_fields(entity_type, id, entity) {
+                    let type_name = match relation_type(entity_type, &field_name) {
+                        Some(type_name) => type_name,
+                        None => continue,
+                    };
+                    let references = match value {
+                        Value::List(values) => values,
+                        value => vec![value],
+                    };
+
+                    for reference in references {
+                        if let Some(to) = self.referenced_entity(&type_name, &reference) {
+                            edges.insert(Edge {
+                                from: (entity_type.clone(), id.clone()),
+                                to,
+                                field_name: field_name.clone(),
+                                derived: is_derived(entity_type, &field_name),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        edges
+    }
+
+    /// Exports the store as a graph, with an entity per node and an edge for every
+    /// reference from a relation field. References resolved from `@derivedFrom` fields
+    /// are dashed and references to entities missing from the store point to red nodes.
+    pub(crate) fn relation_graph(&self, format: GraphFormat) -> String {
+        let edges = self.relation_edges();
+
+        // Nodes are sorted by type and id, so the output is the same on every run.
+        let mut nodes: BTreeMap<(String, String), bool> = self
+            .entities()
+            .iter()
+            .flat_map(|(entity_type, entities)| {
+                entities
+                    .keys()
+                    .map(move |id| ((entity_type.clone(), id.clone()), true))
+            })
+            .collect();
+        for edge in &edges {
+            nodes.entry(edge.to.clone()).or_insert(false);
+        }
+        let node_names: BTreeMap<&(String, String), String> = nodes
+            .keys()
+            .enumerate()
+            .map(|(i, node)| (node, format!("n{}", i)))
+            .collect();
+
+        let mut lines = vec![];
+        match format {
+            GraphFormat::Dot => {
+                lines.push("digraph store {".to_owned());
+                lines.push("  node [shape=box];".to_owned());
+                for ((entity_type, id), stored) in &nodes {
+                    let style = if *stored { "" } else { ", color=red" };
+                    lines.push(format!(
+                        "  \"{}:{}\" [label=\"{}\\n{}\"{}];",
+                        escape_dot(entity_type),
+                        escape_dot(id),
+                        escape_dot(entity_type),
+                        escape_dot(id),
+                        style
+                    ));
+                }
+                for edge in &edges {
+                    let style = if edge.derived { ", style=dashed" } else { "" };
+                    lines.push(format!(
+                        "  \"{}:{}\" -> \"{}:{}\" [label=\"{}\"{}];",
+                        escape_dot(&edge.from.0),
+                        escape_dot(&edge.from.1),
+                        escape_dot(&edge.to.0),
+                        escape_dot(&edge.to.1),
+                        escape_dot(&edge.field_name),
+                        style
+                    ));
+                }
+                lines.push("}".to_owned());
+            }
+            GraphFormat::Mermaid => {
+                lines.push("graph LR".to_owned());
+                for (node, stored) in &nodes {
+                    lines.push(format!(
+                        "  {}[\"{}<br/>{}\"]",
+                        node_names[node],
+                        escape_mermaid(&node.0),
+                        escape_mermaid(&node.1)
+                    ));
+                    if !stored {
+                        lines.push(format!("  style {} stroke:red", node_names[node]));
+                    }
+                }
+                for edge in &edges {
+                    let arrow = if edge.derived { "-.->" } else { "-->" };
+                    lines.push(format!(
+                        "  {} {}|{}| {}",
+                        node_names[&edge.from],
+                        arrow,
+                        escape_mermaid(&edge.field_name),
+                        node_names[&edge.to]
+                    ));
+                }
+            }
+        }
+
+        lines.join("\n")
+    }
+}
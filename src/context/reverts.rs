@@ -0,0 +1,108 @@
+use std::fmt;
+
+use graph::prelude::ethabi::{self, ParamType, Token};
+
+use super::{mock_calls::to_hex, unmocked_calls::input_types};
+
+/// The selector of `Error(string)`, which `revert("reason")` and failed `require`s revert with.
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// The selector of `Panic(uint256)`, which failed `assert`s and overflows revert with.
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// What a contract reverted with.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum RevertData {
+    /// The reason of a `revert("reason")` or a failed `require`.
+    Reason(String),
+    /// The ABI encoded custom error, or `Panic(uint256)`, starting with its selector.
+    Error(Vec<u8>),
+}
+
+impl RevertData {
+    /// Reads the data of a revert, decoding `Error(string)` to its reason.
+    pub(crate) fn from_bytes(data: Vec<u8>) -> Option<Self> {
+        if data.is_empty() {
+            return None;
+        }
+
+        if data.starts_with(&ERROR_SELECTOR) {
+            if let Ok(tokens) = ethabi::decode(&[ParamType::String], &data[4..]) {
+                if let [Token::String(reason)] = tokens.as_slice() {
+                    return Some(RevertData::Reason(reason.clone()));
+                }
+            }
+        }
+        Some(RevertData::Error(data))
+    }
+}
+
+impl fmt::Display for RevertData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RevertData::Reason(reason) => write!(f, "{:?}", reason),
+            RevertData::Error(data) if data.starts_with(&PANIC_SELECTOR) => {
+                match ethabi::decode(&[ParamType::Uint(256)], &data[4..]).as_deref() {
+                    Ok([Token::Uint(code)]) => write!(f, "Panic(0x{:x})", code),
+                    _ => write!(f, "0x{}", to_hex(data)),
+                }
+            }
+            RevertData::Error(data) => write!(f, "0x{}", to_hex(data)),
+        }
+    }
+}
+
+/// How a contract call ended.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum CallOutcome {
+    Returned(Vec<Token>),
+    /// The call reverted, which `try_` calls handle, with what it reverted with if known.
+    Reverted(Option<RevertData>),
+    /// The call could not be made, like when the node is unavailable. Unlike a revert,
+    /// `try_` calls don't handle it and the handler fails.
+    Failed(String),
+}
+
+impl CallOutcome {
+    /// The outcome of a call mocked with a return value, or to revert without a reason.
+    pub(crate) fn mocked(return_value: Vec<Token>, reverts: bool) -> Self {
+        if reverts {
+            CallOutcome::Reverted(None)
+        } else {
+            CallOutcome::Returned(return_value)
+        }
+    }
+}
+
+/// Encodes a custom error like a contract reverting with it: the selector of the error
+/// signature followed by the ABI encoded arguments.
+pub(crate) fn custom_error(
+    error_signature: &str,
+    error_args: &[Token],
+) -> Result<RevertData, String> {
+    let name = error_signature.split('(').next().unwrap_or_default();
+    let param_types = input_types(error_signature);
+
+    if param_types.len() != error_args.len() {
+        return Err(format!(
+            "{} expected {} arguments, but received {}",
+            error_signature,
+            param_types.len(),
+            error_args.len()
+        ));
+    }
+    for (index, (param_type, arg)) in param_types.iter().zip(error_args).enumerate() {
+        if !arg.type_check(param_type) {
+            return Err(format!(
+                "{} mismatch at position {}: expected `{}`, but received {:?}",
+                error_signature,
+                index + 1,
+                param_type,
+                arg
+            ));
+        }
+    }
+
+    let mut data = ethabi::short_signature(name, &param_types).to_vec();
+    data.extend(ethabi::encode(error_args));
+    Ok(RevertData::Error(data))
+}
@@ -0,0 +1,124 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use graph::blockchain::Blockchain;
+use serde_json::Value as JsonValue;
+
+use super::{fixtures, MatchstickInstanceContext};
+use crate::logging;
+
+type StoreJson = BTreeMap<String, BTreeMap<String, BTreeMap<String, JsonValue>>>;
+
+/// Snapshots are kept next to the tests, in `<testsFolder>/__snapshots__/<name>.json`.
+fn snapshot_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::new();
+    crate::TESTS_LOCATION.with(|location| {
+        path = location
+            .borrow()
+            .join("__snapshots__")
+            .join(format!("{}.json", name))
+    });
+    path
+}
+
+fn write_snapshot(path: &Path, store: &StoreJson) {
+    let content = serde_json::to_string_pretty(store).unwrap_or_else(|err| logging::critical!(err));
+
+    path.parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(path, content + "\n"))
+        .unwrap_or_else(|err| logging::critical!("Could not write snapshot {:?}: {}", path, err));
+}
+
+/// Lists the differences between the snapshot and the current store, one per line:
+/// `-` for what is only in the snapshot, `+` for what is only in the store
+/// and `~` for fields with a different value.
+fn diff_stores(snapshot: &StoreJson, store: &StoreJson) -> Vec<String> {
+    let mut diff = vec![];
+    let empty = BTreeMap::new();
+
+    let entity_types: BTreeSet<&String> = snapshot.keys().chain(store.keys()).collect();
+
+    for entity_type in entity_types {
+        let expected = snapshot.get(entity_type).unwrap_or(&empty);
+        let actual = store.get(entity_type).unwrap_or(&empty);
+
+        for (id, expected_entity) in expected {
+            match actual.get(id) {
+                None => diff.push(format!("- {}[{}]", entity_type, id)),
+                Some(actual_entity) => {
+                    for (field, expected_value) in expected_entity {
+                        match actual_entity.get(field) {
+                            None => diff.push(format!(
+                                "- {}[{}].{}: {}",
+                                entity_type, id, field, expected_value
+                            )),
+                            Some(actual_value) if actual_value != expected_value => {
+                                diff.push(format!(
+                                    "~ {}[{}].{}: {} -> {}",
+                                    entity_type, id, field, expected_value, actual_value
+                                ))
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    for (field, actual_value) in actual_entity {
+                        if !expected_entity.contains_key(field) {
+                            diff.push(format!(
+                                "+ {}[{}].{}: {}",
+                                entity_type, id, field, actual_value
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        for id in actual.keys() {
+            if !expected.contains_key(id) {
+                diff.push(format!("+ {}[{}]", entity_type, id));
+            }
+        }
+    }
+
+    diff
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Compares the store with the named snapshot. Missing snapshots are written,
+    /// just like all snapshots when running with `--update-snapshots`.
+    pub(crate) fn matches_snapshot(&self, name: &str) -> bool {
+        let path = snapshot_path(name);
+        let store = fixtures::store_to_json(&self.entities());
+
+        if crate::UPDATE_SNAPSHOTS.with(|update| *update.borrow()) || !path.exists() {
+            write_snapshot(&path, &store);
+            logging::info!("Snapshot '{}' written to {:?}", name, path);
+            return true;
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap_or_else(|err| {
+            logging::critical!(
+                "Something went wrong while trying to read `{:?}`: {}",
+                path,
+                err,
+            )
+        });
+        let snapshot: StoreJson = serde_json::from_str(&content).unwrap_or_else(|err| {
+            logging::critical!("Snapshot {:?} could not be parsed: {}", path, err)
+        });
+
+        let diff = diff_stores(&snapshot, &store);
+        if !diff.is_empty() {
+            logging::error!(
+                "(assert.storeMatchesSnapshot) Store does not match snapshot '{}':\n{}\nRun with `--update-snapshots` to update it.",
+                name,
+                diff.join("\n")
+            );
+            return false;
+        }
+
+        true
+    }
+}
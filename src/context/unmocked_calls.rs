@@ -0,0 +1,283 @@
+use graph::{
+    blockchain::Blockchain,
+    prelude::{
+        ethabi::{Address, ParamType, Token, Uint},
+        BigInt,
+    },
+};
+
+use super::{
+    collect_types, get_kind,
+    mock_calls::{describe_tokens, format_token, to_hex, ArgMatcher, CallPattern},
+    MatchstickInstanceContext,
+};
+
+/// What `ethereum.call` does for a call that isn't mocked. Set with `unmockedCalls`
+/// in matchstick.yaml, or for a single test with `setUnmockedCallPolicy`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnmockedCallPolicy {
+    /// Fails the test, explaining how to mock the call.
+    Fail,
+    /// Returns `null`, so `try_` calls take the reverted branch.
+    Revert,
+    /// Returns the zero value of every return type of the function.
+    Zero,
+}
+
+impl UnmockedCallPolicy {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "fail" => Ok(UnmockedCallPolicy::Fail),
+            "revert" => Ok(UnmockedCallPolicy::Revert),
+            "zero" => Ok(UnmockedCallPolicy::Zero),
+            _ => Err(format!(
+                "Unknown policy for unmocked calls '{}', expected `fail`, `revert` or `zero`.",
+                name
+            )),
+        }
+    }
+}
+
+/// How many of the mocks closest to a call that isn't mocked are listed in the error.
+const CLOSEST_MOCKS: usize = 3;
+
+/// The parameter types of a function signature like `balanceOf(address):(uint256)`.
+pub(crate) fn input_types(fn_signature: &str) -> Vec<ParamType> {
+    let inputs = fn_signature
+        .split_once('(')
+        .map(|(_, rest)| rest.split_once("):").map_or(rest, |(inputs, _)| inputs))
+        .unwrap_or_default();
+    let inputs = inputs.strip_suffix(')').unwrap_or(inputs);
+    collect_types(inputs).into_iter().map(get_kind).collect()
+}
+
+/// The return types of a function signature like `balanceOf(address):(uint256)`.
+pub(crate) fn return_types(fn_signature: &str) -> Vec<ParamType> {
+    fn_signature
+        .split_once("):")
+        .map(|(_, returns)| {
+            let returns = returns.trim();
+            let returns = returns
+                .strip_prefix('(')
+                .and_then(|r| r.strip_suffix(')'))
+                .unwrap_or(returns);
+            collect_types(returns).into_iter().map(get_kind).collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The zero value of an ABI type, like a contract would return for unset storage.
+pub(crate) fn zero_token(param_type: &ParamType) -> Token {
+    match param_type {
+        ParamType::Address => Token::Address(Address::zero()),
+        ParamType::Bytes => Token::Bytes(vec![]),
+        ParamType::FixedBytes(size) => Token::FixedBytes(vec![0; *size]),
+        ParamType::Int(_) => Token::Int(Uint::zero()),
+        ParamType::Uint(_) => Token::Uint(Uint::zero()),
+        ParamType::Bool => Token::Bool(false),
+        ParamType::String => Token::String(String::new()),
+        ParamType::Array(_) => Token::Array(vec![]),
+        ParamType::FixedArray(inner, size) => Token::FixedArray(vec![zero_token(inner); *size]),
+        ParamType::Tuple(components) => Token::Tuple(components.iter().map(zero_token).collect()),
+    }
+}
+
+/// Writes a token as the AssemblyScript expression creating the same `ethereum.Value`.
+fn ethereum_value(token: &Token) -> String {
+    let values = |tokens: &[Token]| {
+        tokens
+            .iter()
+            .map(ethereum_value)
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    match token {
+        Token::Address(address) => format!(
+            "ethereum.Value.fromAddress(Address.fromString(\"{:?}\"))",
+            address
+        ),
+        Token::Bytes(bytes) => format!(
+            "ethereum.Value.fromBytes(Bytes.fromHexString(\"0x{}\"))",
+            to_hex(bytes)
+        ),
+        Token::FixedBytes(bytes) => format!(
+            "ethereum.Value.fromFixedBytes(Bytes.fromHexString(\"0x{}\"))",
+            to_hex(bytes)
+        ),
+        Token::Int(value) => format!(
+            "ethereum.Value.fromSignedBigInt(BigInt.fromString(\"{}\"))",
+            BigInt::from_signed_u256(value)
+        ),
+        Token::Uint(value) => format!(
+            "ethereum.Value.fromUnsignedBigInt(BigInt.fromString(\"{}\"))",
+            value
+        ),
+        Token::Bool(value) => format!("ethereum.Value.fromBoolean({})", value),
+        Token::String(value) => format!("ethereum.Value.fromString({:?})", value),
+        Token::Array(tokens) => format!("ethereum.Value.fromArray([{}])", values(tokens)),
+        Token::FixedArray(tokens) => {
+            format!("ethereum.Value.fromFixedSizedArray([{}])", values(tokens))
+        }
+        Token::Tuple(tokens) => format!(
+            "ethereum.Value.fromTuple(changetype<ethereum.Tuple>([{}]))",
+            values(tokens)
+        ),
+    }
+}
+
+/// A mock that comes close to a call, with what it would take for the mock to match the call.
+struct CloseMock {
+    description: String,
+    differences: Vec<String>,
+}
+
+impl CloseMock {
+    fn new(
+        pattern: &CallPattern,
+        contract_address: &Address,
+        fn_signature: &str,
+        args: &[Token],
+    ) -> Self {
+        let mut differences = vec![];
+
+        if let Some(mocked) = pattern.contract_address {
+            if mocked != *contract_address {
+                differences.push(format!(
+                    "contract address: mocked {:?}, called {:?}",
+                    mocked, contract_address
+                ));
+            }
+        }
+        if pattern.fn_signature != fn_signature {
+            differences.push(format!(
+                "signature: mocked `{}`, called `{}`",
+                pattern.fn_signature, fn_signature
+            ));
+        }
+        if pattern.args.len() != args.len() {
+            differences.push(format!(
+                "number of arguments: mocked {}, called {}",
+                pattern.args.len(),
+                args.len()
+            ));
+        } else {
+            for (index, (matcher, called)) in pattern.args.iter().zip(args).enumerate() {
+                if !matcher.matches(called) {
+                    differences.push(format!(
+                        "argument {}: mocked {}, called {}",
+                        index + 1,
+                        matcher,
+                        format_token(called)
+                    ));
+                }
+            }
+        }
+
+        CloseMock {
+            description: pattern.describe(),
+            differences,
+        }
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// The mocks closest to a call, those with the fewest differences from it first.
+    /// Calls mocked by their exact arguments are compared like a `mockFunctionMatching`
+    /// with every argument matched by its value.
+    fn close_mocks(
+        &self,
+        contract_address: &Address,
+        fn_signature: &str,
+        args: &[Token],
+    ) -> Vec<CloseMock> {
+        let exact_patterns = self.mocked_functions.values().map(|mock| CallPattern {
+            contract_address: Some(mock.contract_address),
+            fn_name: mock.fn_name.clone(),
+            fn_signature: mock.fn_signature.clone(),
+            args: mock.args.iter().cloned().map(ArgMatcher::Equals).collect(),
+        });
+        let matching_patterns = self
+            .call_matchers
+            .iter()
+            .map(|matcher| matcher.pattern.clone());
+
+        let mut mocks: Vec<CloseMock> = exact_patterns
+            .chain(matching_patterns)
+            .map(|pattern| CloseMock::new(&pattern, contract_address, fn_signature, args))
+            .collect();
+        mocks.sort_by(|left, right| {
+            left.differences
+                .len()
+                .cmp(&right.differences.len())
+                .then_with(|| left.description.cmp(&right.description))
+        });
+        mocks.truncate(CLOSEST_MOCKS);
+        mocks
+    }
+
+    /// Lists the calls that weren't mocked in the current test, when the policy let them through.
+    pub(crate) fn unmocked_calls_warning(&self) -> Option<String> {
+        let unmocked: Vec<String> = self
+            .calls
+            .iter()
+            .filter(|call| !call.mocked)
+            .map(|call| format!("  {}", call.describe()))
+            .collect();
+
+        (!unmocked.is_empty()).then(|| {
+            format!(
+                "{} contract calls were not mocked:\n{}",
+                unmocked.len(),
+                unmocked.join("\n")
+            )
+        })
+    }
+
+    /// Explains why a contract call has no mocked return value: the call itself, the mocks
+    /// closest to it with how they differ from it and a snippet mocking the call.
+    pub(crate) fn unmocked_call_error(
+        &self,
+        contract_address: &Address,
+        fn_name: &str,
+        fn_signature: &str,
+        args: &[Token],
+    ) -> String {
+        let mut lines = vec![
+            "No mocked function matches the contract call:".to_owned(),
+            format!("  contract address: {:?}", contract_address),
+            format!("  function: {}", fn_signature),
+            format!("  arguments: [{}]", describe_tokens(args)),
+            String::new(),
+        ];
+
+        let close_mocks = self.close_mocks(contract_address, fn_signature, args);
+        if close_mocks.is_empty() {
+            lines.push("No functions are mocked.".to_owned());
+        } else {
+            lines.push("Closest mocks:".to_owned());
+            for mock in close_mocks {
+                lines.push(format!("  {}", mock.description));
+                for difference in mock.differences {
+                    lines.push(format!("    - {}", difference));
+                }
+            }
+        }
+
+        let return_values: Vec<String> = return_types(fn_signature)
+            .iter()
+            .map(|param_type| ethereum_value(&zero_token(param_type)))
+            .collect();
+        let arg_values: Vec<String> = args.iter().map(ethereum_value).collect();
+        lines.push(String::new());
+        lines.push("To mock the call, add:".to_owned());
+        lines.push(format!(
+            "  createMockedFunction(Address.fromString(\"{:?}\"), \"{}\", \"{}\")",
+            contract_address, fn_name, fn_signature
+        ));
+        lines.push(format!("    .withArgs([{}])", arg_values.join(", ")));
+        lines.push(format!("    .returns([{}])", return_values.join(", ")));
+
+        lines.join("\n")
+    }
+}
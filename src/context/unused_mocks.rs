@@ -0,0 +1,78 @@
+use graph::blockchain::Blockchain;
+
+use super::{
+    mock_calls::{describe_tokens, MockId},
+    MatchstickInstanceContext,
+};
+
+/// What happens after a test that mocked contract calls it never made.
+/// Set with `unusedMocks` in matchstick.yaml.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnusedMockPolicy {
+    /// Doesn't look for unused mocks.
+    Ignore,
+    /// Lists the unused mocks after the test.
+    Warn,
+    /// Fails the test, listing the unused mocks.
+    Fail,
+}
+
+impl UnusedMockPolicy {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "ignore" => Ok(UnusedMockPolicy::Ignore),
+            "warn" => Ok(UnusedMockPolicy::Warn),
+            "fail" => Ok(UnusedMockPolicy::Fail),
+            _ => Err(format!(
+                "Unknown policy for unused mocks '{}', expected `ignore`, `warn` or `fail`.",
+                name
+            )),
+        }
+    }
+}
+
+impl<C: Blockchain> MatchstickInstanceContext<C> {
+    /// Keeps track of a mock created in the current test.
+    pub(crate) fn register_mock(&mut self, mock_id: MockId) {
+        if !self.test_mocks.contains(&mock_id) {
+            self.test_mocks.push(mock_id);
+        }
+    }
+
+    fn describe_mock(&self, mock_id: &MockId) -> String {
+        match mock_id {
+            MockId::Function(fn_id) => self.mocked_functions.get(fn_id).map_or_else(
+                || fn_id.clone(),
+                |mock| {
+                    format!(
+                        "{:?}.{}({})",
+                        mock.contract_address,
+                        mock.fn_signature,
+                        describe_tokens(&mock.args)
+                    )
+                },
+            ),
+            MockId::Matcher(index) => self.call_matchers[*index].pattern.describe(),
+        }
+    }
+
+    /// Lists the mocks created in the current test that no contract call was served from.
+    /// Mocks created outside of tests, like those of `contractMocks`, are shared by the tests
+    /// of a suite and not listed.
+    pub(crate) fn unused_mocks_report(&self) -> Option<String> {
+        let unused: Vec<String> = self
+            .test_mocks
+            .iter()
+            .filter(|mock_id| !self.served_mocks.contains(mock_id))
+            .map(|mock_id| format!("  {}", self.describe_mock(mock_id)))
+            .collect();
+
+        (!unused.is_empty()).then(|| {
+            format!(
+                "{} mocks were never called:\n{}",
+                unused.len(),
+                unused.join("\n")
+            )
+        })
+    }
+}
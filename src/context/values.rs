@@ -0,0 +1,133 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+
+use graph::{
+    data::store::Value,
+    prelude::{BigDecimal, BigInt},
+};
+
+use super::entity_schema;
+use crate::logging;
+
+/// Compares two store values by what they represent rather than by their representation,
+/// so that `1.50` equals `1.5` and an `Int` equals a `BigInt` of the same value.
+pub(crate) fn values_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Int(l), Value::BigInt(r)) | (Value::BigInt(r), Value::Int(l)) => {
+            &BigInt::from(*l) == r
+        }
+        (Value::List(l), Value::List(r)) => {
+            l.len() == r.len() && l.iter().zip(r.iter()).all(|(l, r)| values_equal(l, r))
+        }
+        _ => left == right,
+    }
+}
+
+/// Whether the actual number is at most `tolerance` away from the expected one.
+/// Values that aren't numbers are compared with `values_equal`.
+pub(crate) fn values_within(expected: &Value, actual: &Value, tolerance: &BigDecimal) -> bool {
+    match (expected, actual) {
+        (Value::List(e), Value::List(a)) => {
+            e.len() == a.len()
+                && e.iter()
+                    .zip(a.iter())
+                    .all(|(e, a)| values_within(e, a, tolerance))
+        }
+        _ => match (as_decimal(expected), as_decimal(actual)) {
+            (Some(e), Some(a)) => a >= e.clone() - tolerance.clone() && a <= e + tolerance.clone(),
+            _ => values_equal(expected, actual),
+        },
+    }
+}
+
+/// Orders two values of the same kind, numbers by their value regardless of their type.
+/// Returns `None` for values that can't be ordered, like a string and a number.
+pub(crate) fn compare_values(left: &Value, right: &Value) -> Option<Ordering> {
+    if let (Some(l), Some(r)) = (as_decimal(left), as_decimal(right)) {
+        return l.partial_cmp(&r);
+    }
+
+    match (left, right) {
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        (Value::Bytes(l), Value::Bytes(r)) => Some(l.as_slice().cmp(r.as_slice())),
+        (Value::Bool(l), Value::Bool(r)) => Some(l.cmp(r)),
+        _ => None,
+    }
+}
+
+fn as_decimal(value: &Value) -> Option<BigDecimal> {
+    match value {
+        Value::Int(_) | Value::BigInt(_) | Value::BigDecimal(_) => {
+            BigDecimal::from_str(&value.to_string()).ok()
+        }
+        _ => None,
+    }
+}
+
+/// Whether the value of an entity field matches the expected value written as a string.
+/// The expected value is parsed according to the type of the field in `schema.graphql`,
+/// falling back to comparing strings for fields that are not in the schema.
+pub(crate) fn field_matches(
+    entity_type: &str,
+    field_name: &str,
+    actual: &Value,
+    expected: &str,
+    tolerance: Option<&BigDecimal>,
+) -> bool {
+    let parsed = entity_schema::field_definition(entity_type, field_name)
+        .and_then(|field| entity_schema::value_from_str(&field.field_type, expected).ok());
+    let equals = match (&parsed, tolerance) {
+        (Some(parsed), Some(tolerance)) => values_within(parsed, actual, tolerance),
+        (Some(parsed), None) => values_equal(parsed, actual),
+        (None, _) => false,
+    };
+
+    equals || actual.to_string() == expected
+}
+
+/// Lists the fields in which the actual entity differs from the expected one.
+/// A field set to `null` is considered the same as a missing field.
+pub(crate) fn diff_entities(
+    expected: &HashMap<String, Value>,
+    actual: &HashMap<String, Value>,
+) -> Vec<String> {
+    let fields: BTreeSet<&String> = expected.keys().chain(actual.keys()).collect();
+
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            match (
+                expected.get(field).filter(|v| !matches!(v, Value::Null)),
+                actual.get(field).filter(|v| !matches!(v, Value::Null)),
+            ) {
+                (Some(expected), None) => Some(format!(
+                    "missing field '{}', expected '{}'",
+                    field, expected
+                )),
+                (None, Some(actual)) => Some(format!("extra field '{}' with '{}'", field, actual)),
+                (Some(expected), Some(actual)) if !values_equal(expected, actual) => Some(format!(
+                    "field '{}' expected '{}', but was '{}'",
+                    field, expected, actual
+                )),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Reads a count or a block number the mappings pass as an `i32`, which can't be negative.
+/// `what` names the value in the error, e.g. "Expected count".
+pub(crate) fn non_negative(function_name: &str, what: &str, value: u32) -> u32 {
+    let number = value as i32;
+    if number < 0 {
+        logging::critical!(
+            "({}) {} should not be negative, got {}.",
+            function_name,
+            what,
+            number
+        );
+    }
+
+    number as u32
+}
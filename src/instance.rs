@@ -23,6 +23,7 @@ use graph_runtime_wasm::{
 use wasmtime::Trap;
 
 use crate::subgraph_store::MockSubgraphStore;
+use crate::writable_store::MockWritableStore;
 use crate::{context::MatchstickInstanceContext, logging};
 
 /// The Matchstick Instance is simply a wrapper around WASM Instance and
@@ -60,7 +61,8 @@ impl<C: Blockchain> MatchstickInstance<C> {
             allow_non_deterministic_ipfs: true,
         };
 
-        let mock_subgraph_store = MockSubgraphStore {};
+        let store = Arc::new(MockWritableStore::new());
+        let mock_subgraph_store = MockSubgraphStore::new(store.clone());
 
         let valid_module = Arc::new(
             ValidModule::new(
@@ -84,6 +86,7 @@ impl<C: Blockchain> MatchstickInstance<C> {
                 Arc::from(mock_subgraph_store),
                 Version::new(0, 0, 6),
             ),
+            store,
             host_metrics,
             None,
             experimental_features,
@@ -107,6 +110,7 @@ impl<C: Blockchain> MatchstickInstance<C> {
     pub fn from_valid_module_with_ctx(
         valid_module: Arc<ValidModule>,
         ctx: MappingContext<C>,
+        store: Arc<MockWritableStore>,
         host_metrics: Arc<HostMetrics>,
         timeout: Option<Duration>,
         experimental_features: ExperimentalFeatures,
@@ -160,6 +164,7 @@ impl<C: Blockchain> MatchstickInstance<C> {
                     let host_metrics = host_metrics.cheap_clone();
                     let timeout_stopwatch = timeout_stopwatch.cheap_clone();
                     let ctx = ctx.cheap_clone();
+                    let store = store.cheap_clone();
                     let gas = gas.cheap_clone();
                     linker.func(
                         module,
@@ -178,8 +183,9 @@ impl<C: Blockchain> MatchstickInstance<C> {
                                         timeout,
                                         timeout_stopwatch.cheap_clone(),
                                         experimental_features.clone()
-                                    ).unwrap())
-                                )
+                                    ).unwrap(),
+                                    store.cheap_clone(),
+                                ))
                             }
 
                             let instance = instance.as_mut().unwrap();
@@ -300,9 +306,85 @@ impl<C: Blockchain> MatchstickInstance<C> {
             return_value_ptr,
             reverts
         );
+        link!(
+            "mockFunctionRevertsWithReason",
+            mock_function_reverts_with_reason,
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+            reason_ptr
+        );
+        link!(
+            "mockFunctionRevertsWithError",
+            mock_function_reverts_with_error,
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+            error_signature_ptr,
+            error_args_ptr
+        );
+        link!(
+            "mockFunctionFails",
+            mock_function_fails,
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+            message_ptr
+        );
+        link!(
+            "mockFunctionOnce",
+            mock_function_once,
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+            return_value_ptr,
+            reverts
+        );
+        link!(
+            "mockFunctionAtBlock",
+            mock_function_at_block,
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+            return_value_ptr,
+            reverts,
+            block_number
+        );
+        link!(
+            "mockContractFunction",
+            mock_contract_function,
+            contract_name_ptr,
+            contract_address_ptr,
+            fn_signature_ptr,
+            fn_args_ptr,
+            return_value_ptr,
+            reverts
+        );
+        link!(
+            "setUnmockedCallPolicy",
+            set_unmocked_call_policy,
+            policy_ptr
+        );
+        link!(
+            "mockFunctionMatching",
+            mock_function_matching,
+            contract_address_ptr,
+            fn_name_ptr,
+            fn_signature_ptr,
+            arg_matchers_ptr,
+            return_value_ptr,
+            reverts
+        );
 
         link!("clearStore", clear_store,);
         link!("logStore", log_store,);
+        link!("storeGraph", store_graph, format_ptr);
+        link!("logStoreGraph", log_store_graph, format_ptr);
         link!(
             "store.get",
             mock_store_get,
@@ -460,6 +542,22 @@ impl<C: Blockchain> MatchstickInstance<C> {
             field_name_ptr,
             expected_val_ptr
         );
+        link!(
+            "_assert.fieldEqualsWithTolerance",
+            assert_field_equals_with_tolerance,
+            entity_type_ptr,
+            id_ptr,
+            field_name_ptr,
+            expected_val_ptr,
+            tolerance_ptr
+        );
+        link!(
+            "_assert.entityEquals",
+            assert_entity_equals,
+            entity_type_ptr,
+            id_ptr,
+            expected_ptr
+        );
         link!("_assert.equals", assert_equals, expected_ptr, actual_ptr);
         link!(
             "_assert.notInStore",
@@ -467,8 +565,85 @@ impl<C: Blockchain> MatchstickInstance<C> {
             entity_type_ptr,
             id_ptr
         );
+        link!(
+            "_assert.storeMatchesSnapshot",
+            assert_store_matches_snapshot,
+            name_ptr
+        );
 
         link!("countEntities", count_entities, entity_type);
+        link!("queryEntities", query_entities, entity_type_ptr, query_ptr);
+        link!("queryGraphQL", query_graphql, query_ptr, variables_ptr);
+        link!(
+            "_assert.graphQLResultEquals",
+            assert_graphql_result_equals,
+            query_ptr,
+            variables_ptr,
+            expected_ptr
+        );
+        link!(
+            "_assert.entityCount",
+            assert_entity_count,
+            entity_type_ptr,
+            expected_count
+        );
+        link!(
+            "_assert.entitiesWhere",
+            assert_entities_where,
+            entity_type_ptr,
+            filter_ptr,
+            expected_count
+        );
+        link!("loadFixture", load_fixture, file_name_ptr);
+        link!("loadContractMocks", load_contract_mocks, file_name_ptr);
+        link!("mockBlock", mock_block, block_number, event_ptr);
+        link!("revertToBlock", revert_to_block, block_number);
+        link!("useEntityCache", use_entity_cache, enabled);
+        link!("flushEntityCache", flush_entity_cache,);
+        link!("mergeStoreSet", merge_store_set, enabled);
+        link!(
+            "_assert.dataSourceCount",
+            assert_data_source_count,
+            template_name_ptr,
+            expected_count
+        );
+        link!(
+            "_assert.contractCalled",
+            assert_contract_called,
+            contract_address_ptr,
+            fn_signature_ptr,
+            arg_matchers_ptr,
+            times
+        );
+        link!("_assert.noUnexpectedCalls", assert_no_unexpected_calls,);
+        link!(
+            "getEntityAtBlock",
+            get_entity_at_block,
+            entity_type_ptr,
+            id_ptr,
+            block_number
+        );
+        link!(
+            "logEntityHistory",
+            log_entity_history,
+            entity_type_ptr,
+            id_ptr
+        );
+        link!(
+            "_assert.entityChangeCount",
+            assert_entity_change_count,
+            entity_type_ptr,
+            id_ptr,
+            expected_count
+        );
+        link!(
+            "_assert.fieldHistory",
+            assert_field_history,
+            entity_type_ptr,
+            id_ptr,
+            field_name_ptr,
+            expected_values_ptr
+        );
 
         // Linking gas function
         let gas = gas.cheap_clone();
@@ -493,6 +668,7 @@ impl<C: Blockchain> MatchstickInstance<C> {
                     timeout_stopwatch,
                     experimental_features,
                 )?,
+                store,
             ));
         }
 
@@ -1,6 +1,6 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use colored::Colorize;
@@ -9,6 +9,7 @@ use graph_chain_ethereum::Chain;
 
 use crate::compiler::Compiler;
 use crate::config::MatchstickConfig;
+use crate::context::{EthRpc, UnmockedCallPolicy, UnusedMockPolicy};
 use crate::instance::MatchstickInstance;
 use crate::test_suite::{TestResult, TestSuite};
 
@@ -32,6 +33,16 @@ thread_local! {
     pub(crate) static SCHEMA_LOCATION: RefCell<PathBuf> = RefCell::new(PathBuf::new());
     pub(crate) static TESTS_LOCATION: RefCell<PathBuf> = RefCell::new(PathBuf::new());
     pub(crate) static LIBS_LOCATION: RefCell<PathBuf> = RefCell::new(PathBuf::new());
+    pub(crate) static FIXTURES_LOCATION: RefCell<PathBuf> = RefCell::new(PathBuf::new());
+    pub(crate) static STORE_DUMP_LOCATION: RefCell<Option<PathBuf>> = RefCell::new(None);
+    pub(crate) static UPDATE_SNAPSHOTS: RefCell<bool> = RefCell::new(false);
+    pub(crate) static ENTITY_CACHE: RefCell<bool> = RefCell::new(false);
+    pub(crate) static MERGE_STORE_SET: RefCell<bool> = RefCell::new(true);
+    pub(crate) static CONTRACT_MOCKS: RefCell<Vec<PathBuf>> = RefCell::new(vec![]);
+    pub(crate) static ABI_FILES: RefCell<HashMap<String, PathBuf>> = RefCell::new(HashMap::new());
+    pub(crate) static UNMOCKED_CALLS: RefCell<UnmockedCallPolicy> = RefCell::new(UnmockedCallPolicy::Fail);
+    pub(crate) static ETH_RPC: RefCell<Option<EthRpc>> = RefCell::new(None);
+    pub(crate) static UNUSED_MOCKS: RefCell<UnusedMockPolicy> = RefCell::new(UnusedMockPolicy::Ignore);
 }
 
 fn main() {
@@ -44,8 +55,44 @@ fn main() {
     let config = MatchstickConfig::from("matchstick.yaml");
 
     SCHEMA_LOCATION.with(|path| *path.borrow_mut() = PathBuf::from(&schema_location));
+    ABI_FILES.with(|files| {
+        *files.borrow_mut() = parser::collect_abis("subgraph.yaml")
+            .into_iter()
+            .map(|(name, file)| (name, PathBuf::from(file)))
+            .collect()
+    });
     TESTS_LOCATION.with(|path| *path.borrow_mut() = PathBuf::from(&config.tests_path));
     LIBS_LOCATION.with(|path| *path.borrow_mut() = PathBuf::from(&config.libs_path));
+    FIXTURES_LOCATION.with(|path| *path.borrow_mut() = PathBuf::from(&config.fixtures_path));
+    CONTRACT_MOCKS.with(|files| {
+        *files.borrow_mut() = config
+            .contract_mocks
+            .iter()
+            .map(|file| PathBuf::from(&config.fixtures_path).join(file))
+            .collect()
+    });
+    UPDATE_SNAPSHOTS.with(|update| *update.borrow_mut() = matches.is_present("update_snapshots"));
+    ENTITY_CACHE.with(|enabled| *enabled.borrow_mut() = config.entity_cache);
+    MERGE_STORE_SET.with(|enabled| *enabled.borrow_mut() = config.merge_store_set);
+    UNMOCKED_CALLS.with(|policy| *policy.borrow_mut() = config.unmocked_calls);
+    UNUSED_MOCKS.with(|policy| *policy.borrow_mut() = config.unused_mocks);
+    // Calls are only forwarded when there is an endpoint, or recorded calls to replay.
+    if config.rpc_endpoint.is_some() || config.rpc_cache.is_some() {
+        let cache = config.rpc_cache.clone().map_or_else(
+            || PathBuf::from(&config.tests_path).join("rpc_cache.json"),
+            PathBuf::from,
+        );
+        ETH_RPC.with(|rpc| {
+            *rpc.borrow_mut() = Some(EthRpc::new(
+                config.rpc_endpoint.clone(),
+                cache,
+                config.rpc_block,
+            ))
+        });
+    }
+    STORE_DUMP_LOCATION.with(|path| {
+        *path.borrow_mut() = matches.value_of("dump_store_on_failure").map(PathBuf::from)
+    });
 
     logging::log_with_style!(bright_green, "Compiling...\n");
 
@@ -75,7 +122,7 @@ fn main() {
         .collect();
 
     // A test suite abstraction for each instance.
-    let test_suites: HashMap<String, TestSuite> = ms_instances
+    let test_suites: HashMap<String, TestSuite<Chain>> = ms_instances
         .iter()
         .map(|(key, val)| (key.clone(), TestSuite::from(val)))
         .collect();
@@ -105,7 +152,7 @@ ___  ___      _       _         _   _      _
     )
 }
 
-fn run_test_suites(test_suites: HashMap<String, TestSuite>) -> i32 {
+fn run_test_suites(test_suites: HashMap<String, TestSuite<Chain>>) -> i32 {
     logging::log_with_style!(bright_red, "\nIgniting tests 🔥");
 
     let (mut num_passed, mut num_failed) = (0, 0);
@@ -154,6 +201,14 @@ fn run_test_suites(test_suites: HashMap<String, TestSuite>) -> i32 {
                 if !result.logs.is_empty() {
                     logging::default!(result.logs);
                 }
+
+                if let Some(store_dump) = &result.store_dump {
+                    STORE_DUMP_LOCATION.with(|path| {
+                        if let Some(dir) = &*path.borrow() {
+                            dump_store(dir, &suite, &name, store_dump);
+                        }
+                    });
+                }
             }
         }
 
@@ -164,3 +219,26 @@ fn run_test_suites(test_suites: HashMap<String, TestSuite>) -> i32 {
         0
     }
 }
+
+/// Writes the store state of a failed test to `<dir>/<suite>__<test>.json`.
+fn dump_store(dir: &Path, suite: &str, test: &str, store_dump: &str) {
+    let file_name: String = format!("{}__{}", suite, test)
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let file = dir.join(file_name).with_extension("json");
+
+    std::fs::create_dir_all(dir)
+        .and_then(|_| std::fs::write(&file, store_dump))
+        .unwrap_or_else(|err| {
+            logging::critical!("Could not write the store state to {:?}: {}", file, err)
+        });
+
+    logging::info!("Store state written to {:?}", file);
+}
@@ -43,6 +43,27 @@ pub fn extract_string_or(value: &Value, key: &str, default: String) -> String {
         .to_owned()
 }
 
+/// Extracts the boolean value of the passed key from the parsed yaml
+/// Fallbacks to the default value if the key is missing or isn't a boolean
+pub fn extract_bool_or(value: &Value, key: &str, default: bool) -> bool {
+    value.get(key).and_then(|v| v.as_bool()).unwrap_or(default)
+}
+
+/// Extracts the string values of the passed key from the parsed yaml,
+/// which can be a single string or a list of strings
+/// Returns an empty Vec if the key is missing
+pub fn extract_strings(value: &Value, key: &str) -> Vec<String> {
+    match value.get(key) {
+        Some(Value::String(string)) => vec![string.clone()],
+        Some(Value::Sequence(values)) => values
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|v| v.to_owned())
+            .collect(),
+        _ => vec![],
+    }
+}
+
 /// Extracts the value of the passed key as Sequence
 /// Will return an empty Vec if the key is missing
 /// Will panic if the value can't be parsed as Sequence
@@ -93,6 +114,17 @@ pub fn collect_handlers(path: &str) -> HashMap<String, Vec<String>> {
         .collect()
 }
 
+/// Collects the ABI files referenced by the sources declared under dataSources or templates,
+/// keyed by the name of the ABI. Sources sharing an ABI list it only once.
+pub fn collect_abis(path: &str) -> HashMap<String, String> {
+    parse_sources(path)
+        .iter()
+        .filter_map(|source| source.get("mapping"))
+        .flat_map(|mapping| extract_vec(mapping, "abis"))
+        .map(|abi| (extract_string(&abi, "name"), extract_string(&abi, "file")))
+        .collect()
+}
+
 /// Extracts the schema location from subraph.yaml
 /// Will panic if the `schema` or `file` key is missing
 pub fn get_schema_location(path: &str) -> String {
@@ -176,6 +208,19 @@ mod parser_tests {
         assert_eq!(handlers, expected)
     }
 
+    #[test]
+    fn collect_abis_returns_abis_of_all_sources() {
+        let abis = collect_abis("mocks/yamls/subgraph.yaml");
+        let mut expected: HashMap<String, String> = HashMap::new();
+        expected.insert("Gravity".to_owned(), "./abis/Gravity.json".to_owned());
+        expected.insert(
+            "GraphTokenLockWallet".to_owned(),
+            "./abis/GraphTokenLockWallet.json".to_owned(),
+        );
+
+        assert_eq!(abis, expected)
+    }
+
     #[test]
     fn collect_handlers_returns_empty_vec_if_no_handlers() {
         let handlers = collect_handlers("mocks/yamls/subgraph_no_handlers.yaml");
@@ -4,7 +4,7 @@ use std::sync::Arc;
 use async_trait::async_trait;
 use graph::{
     blockchain::BlockPtr,
-    components::store::{DeploymentId, DeploymentLocator, EnsLookup, SubgraphFork},
+    components::store::{DeploymentId, DeploymentLocator, EnsLookup, SubgraphFork, WritableStore},
     data::subgraph::*,
     prelude::{DeploymentHash, StoreError, SubgraphStore},
     slog::Logger,
@@ -12,7 +12,17 @@ use graph::{
 
 use crate::writable_store::MockWritableStore;
 
-pub struct MockSubgraphStore {}
+/// A `SubgraphStore` for the single subgraph under test, handing out the same in-memory
+/// `MockWritableStore` every time it is asked for one.
+pub struct MockSubgraphStore {
+    writable: Arc<MockWritableStore>,
+}
+
+impl MockSubgraphStore {
+    pub fn new(writable: Arc<MockWritableStore>) -> Self {
+        MockSubgraphStore { writable }
+    }
+}
 
 struct DummyStruct {}
 
@@ -29,7 +39,7 @@ impl SubgraphStore for MockSubgraphStore {
     }
 
     fn is_deployed(&self, _id: &DeploymentHash) -> Result<bool, StoreError> {
-        unreachable!()
+        Ok(true)
     }
 
     fn create_subgraph_deployment(
@@ -90,7 +100,7 @@ impl SubgraphStore for MockSubgraphStore {
         &self,
         _subgraph_id: &DeploymentHash,
     ) -> Result<Arc<graph::prelude::Schema>, graph::prelude::StoreError> {
-        unreachable!()
+        Ok(self.writable.input_schema())
     }
 
     fn api_schema(
@@ -108,8 +118,7 @@ impl SubgraphStore for MockSubgraphStore {
         Arc<dyn graph::components::store::WritableStore>,
         graph::components::store::StoreError,
     > {
-        let mock_writable_store = MockWritableStore {};
-        Ok(Arc::from(mock_writable_store))
+        Ok(self.writable.clone())
     }
 
     fn writable_for_network_indexer(
@@ -117,14 +126,14 @@ impl SubgraphStore for MockSubgraphStore {
         _logger: Logger,
         _id: &DeploymentHash,
     ) -> Result<Arc<dyn graph::components::store::WritableStore>, graph::prelude::StoreError> {
-        unreachable!()
+        Ok(self.writable.clone())
     }
 
     fn least_block_ptr(
         &self,
         _id: &DeploymentHash,
     ) -> Result<Option<BlockPtr>, graph::prelude::StoreError> {
-        unreachable!()
+        Ok(self.writable.block_ptr())
     }
 
     fn locators(&self, _hash: &str) -> Result<Vec<DeploymentLocator>, graph::prelude::StoreError> {
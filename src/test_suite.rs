@@ -1,34 +1,56 @@
 use colored::Colorize;
 use graph::blockchain::Blockchain;
-use std::time::Instant;
+use std::{cell::RefCell, rc::Rc, time::Instant};
 use wasmtime::Func;
 
-use crate::{instance::MatchstickInstance, logging};
+use crate::{
+    context::{MatchstickInstanceContext, UnusedMockPolicy},
+    instance::MatchstickInstance,
+    logging,
+};
 
-pub struct Test {
+pub struct Test<C: Blockchain> {
     pub name: String,
     should_fail: bool,
     func: Func,
     before_hooks: Vec<Func>,
     after_hooks: Vec<Func>,
+    /// Handle to the context of the instance the test is run in.
+    instance_ctx: Rc<RefCell<Option<MatchstickInstanceContext<C>>>>,
 }
 
 pub struct TestResult {
     pub passed: bool,
     pub logs: String,
+    /// State of the store right after a failed test, if `--dump-store-on-failure` is used.
+    pub store_dump: Option<String>,
 }
 
-impl Test {
-    fn new(name: String, should_fail: bool, func: Func) -> Self {
+impl<C: Blockchain> Test<C> {
+    fn new(
+        name: String,
+        should_fail: bool,
+        func: Func,
+        instance_ctx: Rc<RefCell<Option<MatchstickInstanceContext<C>>>>,
+    ) -> Self {
         Test {
             name,
             should_fail,
             func,
             before_hooks: vec![],
             after_hooks: vec![],
+            instance_ctx,
         }
     }
 
+    fn instance_ctx(&self) -> std::cell::Ref<'_, MatchstickInstanceContext<C>> {
+        std::cell::Ref::map(self.instance_ctx.borrow(), |ctx| {
+            ctx.as_ref().unwrap_or_else(|| {
+                logging::critical!("Unexpected: MatchstickInstanceContext is 'None'.")
+            })
+        })
+    }
+
     fn call_hooks(hooks: &[Func]) {
         hooks.iter().for_each(|h| {
             h.call(&[]).unwrap_or_else(|err| {
@@ -38,14 +60,17 @@ impl Test {
     }
 
     fn before(&self) {
-        Test::call_hooks(&self.before_hooks);
+        Self::call_hooks(&self.before_hooks);
     }
 
     fn after(&self) {
-        Test::call_hooks(&self.after_hooks);
+        Self::call_hooks(&self.after_hooks);
     }
 
     pub fn run(&self) -> TestResult {
+        if let Some(ctx) = self.instance_ctx.borrow_mut().as_mut() {
+            ctx.reset_test_state();
+        }
         self.before();
 
         // NOTE: Calling a test func should not fail for any other reason than:
@@ -80,6 +105,27 @@ impl Test {
             }
         };
 
+        if let Some(warning) = self.instance_ctx().unmocked_calls_warning() {
+            logging::warning!(warning);
+        }
+
+        // Mocks of a test that failed, or was expected to, may well have been left unused.
+        let unused_mocks = crate::UNUSED_MOCKS.with(|policy| *policy.borrow());
+        let passed = match self.instance_ctx().unused_mocks_report() {
+            Some(report) if passed && !self.should_fail => match unused_mocks {
+                UnusedMockPolicy::Ignore => true,
+                UnusedMockPolicy::Warn => {
+                    logging::warning!(report);
+                    true
+                }
+                UnusedMockPolicy::Fail => {
+                    logging::error!(report);
+                    false
+                }
+            },
+            _ => passed,
+        };
+
         // Convert the elapsed time to milliseconds
         // Seems hacky, might need refactoring
         let elapsed_in_ms = now.elapsed().as_secs_f32() * 1000.0;
@@ -103,17 +149,29 @@ impl Test {
             logging::default!(&logs);
         }
 
+        // The store is captured before the `after` hooks get the chance to clear it.
+        let store_dump =
+            if !passed && crate::STORE_DUMP_LOCATION.with(|path| path.borrow().is_some()) {
+                Some(self.instance_ctx().dump_store())
+            } else {
+                None
+            };
+
         self.after();
 
-        TestResult { passed, logs }
+        TestResult {
+            passed,
+            logs,
+            store_dump,
+        }
     }
 }
 
-pub struct TestSuite {
-    pub tests: Vec<Test>,
+pub struct TestSuite<C: Blockchain> {
+    pub tests: Vec<Test<C>>,
 }
 
-impl<C: Blockchain> From<&MatchstickInstance<C>> for TestSuite {
+impl<C: Blockchain> From<&MatchstickInstance<C>> for TestSuite<C> {
     fn from(matchstick: &MatchstickInstance<C>) -> Self {
         let table = matchstick.instance.get_table("table").unwrap_or_else(|| {
             logging::critical!(
@@ -122,6 +180,15 @@ impl<C: Blockchain> From<&MatchstickInstance<C>> for TestSuite {
             )
         });
 
+        // Contract mocks from the config are loaded before the tests of every suite.
+        if let Some(ctx) = matchstick.instance_ctx.borrow_mut().as_mut() {
+            crate::CONTRACT_MOCKS.with(|files| {
+                for file in files.borrow().iter() {
+                    ctx.insert_contract_mocks(file);
+                }
+            });
+        }
+
         let mut suite = TestSuite { tests: vec![] };
         for (name, should_fail, func_idx) in &matchstick
             .instance_ctx
@@ -146,6 +213,7 @@ impl<C: Blockchain> From<&MatchstickInstance<C>> for TestSuite {
                     .unwrap_funcref()
                     .unwrap()
                     .to_owned(),
+                matchstick.instance_ctx.clone(),
             ))
         }
 
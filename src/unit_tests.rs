@@ -1,26 +1,43 @@
 #[cfg(test)]
 mod unit_tests {
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
     use std::path::PathBuf;
     use std::str::FromStr;
-    use std::sync::Once;
+    use std::sync::{Arc, Once};
 
     use graph::{
-        data::store::Value,
-        prelude::ethabi::{Address, Token},
+        blockchain::BlockPtr,
+        components::store::{EntityType, WritableStore},
+        data::store::{scalar::Bytes, Value},
+        prelude::{
+            ethabi::{self, Address, Token, Uint},
+            o,
+            slog::{Discard, Logger},
+            web3::types::H256,
+            BigDecimal, BigInt, DeploymentHash, Entity, EntityKey, EntityModification,
+            StopwatchMetrics,
+        },
         runtime::{asc_get, asc_new, gas::GasCounter, try_asc_get, AscPtr, AscType},
     };
     use graph_chain_ethereum::{runtime::abi::AscUnresolvedContractCall_0_0_4, Chain};
+    use graph_mock::MockMetricsRegistry;
     use graph_runtime_wasm::asc_abi::class::{
-        Array, AscEnum, AscTypedMap, AscTypedMapEntry, EnumPayload, EthereumValueKind,
+        Array, AscEnum, AscString, AscTypedMap, AscTypedMapEntry, EnumPayload, EthereumValueKind,
         StoreValueKind, TypedArray,
     };
     use serial_test::serial;
 
     use crate::{
-        context::{asc_string_from_str, MatchstickInstanceContext, REVERTS_IDENTIFIER},
+        context::{
+            asc_string_from_str, CallOutcome, EthRpc, MatchstickInstanceContext, RevertData,
+        },
         logging::{accum, flush, LOGS},
-        {MatchstickInstance, SCHEMA_LOCATION},
+        {
+            MatchstickInstance, ABI_FILES, ETH_RPC, SCHEMA_LOCATION, TESTS_LOCATION,
+            UPDATE_SNAPSHOTS,
+        },
     };
 
     static GET_SCHEMA: Once = Once::new();
@@ -39,6 +56,273 @@ mod unit_tests {
             .expect("Couldn't get context from module.")
     }
 
+    fn string_ptr(context: &mut MatchstickInstanceContext<Chain>, s: &str) -> AscPtr<AscString> {
+        AscPtr::alloc_obj(
+            asc_string_from_str(s),
+            &mut context.wasm_ctx,
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create pointer.")
+    }
+
+    fn store_set(
+        context: &mut MatchstickInstanceContext<Chain>,
+        entity_type: &str,
+        id: &str,
+        data: HashMap<String, Value>,
+    ) {
+        let entity_type_ptr = string_ptr(context, entity_type);
+        let id_ptr = string_ptr(context, id);
+        let data_ptr = asc_new(
+            &mut context.wasm_ctx,
+            &Entity::from(data).sorted(),
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create pointer.");
+
+        context
+            .mock_store_set(&GasCounter::new(), entity_type_ptr, id_ptr, data_ptr)
+            .expect("Couldn't call mock_store_set.");
+    }
+
+    fn field_equals(
+        context: &mut MatchstickInstanceContext<Chain>,
+        entity_type: &str,
+        id: &str,
+        field_name: &str,
+        expected_val: &str,
+    ) -> bool {
+        let entity_type_ptr = string_ptr(context, entity_type);
+        let id_ptr = string_ptr(context, id);
+        let field_name_ptr = string_ptr(context, field_name);
+        let expected_val_ptr = string_ptr(context, expected_val);
+
+        context
+            .assert_field_equals(
+                &GasCounter::new(),
+                entity_type_ptr,
+                id_ptr,
+                field_name_ptr,
+                expected_val_ptr,
+            )
+            .expect("Couldn't call assert_field_equals.")
+    }
+
+    fn contract_call_ptr(
+        context: &mut MatchstickInstanceContext<Chain>,
+        contract_address: &str,
+        fn_name: &str,
+        fn_signature: &str,
+        fn_args: &[Token],
+    ) -> u32 {
+        let contract_address =
+            Address::from_str(contract_address).expect("Couldn't create Address.");
+        let contract_address = TypedArray::new(
+            contract_address.as_bytes(),
+            &mut context.wasm_ctx,
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create address.");
+        let unresolved_call = AscUnresolvedContractCall_0_0_4 {
+            contract_name: string_ptr(context, "contractName"),
+            contract_address: AscPtr::alloc_obj(
+                contract_address,
+                &mut context.wasm_ctx,
+                &GasCounter::new(),
+            )
+            .expect("Couldn't create pointer."),
+            function_name: string_ptr(context, fn_name),
+            function_signature: string_ptr(context, fn_signature),
+            function_args: asc_new(&mut context.wasm_ctx, fn_args, &GasCounter::new())
+                .expect("Couldn't create pointer."),
+        };
+        AscPtr::alloc_obj(unresolved_call, &mut context.wasm_ctx, &GasCounter::new())
+            .expect("Couldn't create pointer.")
+            .wasm_ptr()
+    }
+
+    fn ethereum_call(
+        context: &mut MatchstickInstanceContext<Chain>,
+        contract_address: &str,
+        fn_name: &str,
+        fn_signature: &str,
+        fn_args: &[Token],
+    ) -> Option<Vec<Token>> {
+        let call_ptr = contract_call_ptr(context, contract_address, fn_name, fn_signature, fn_args);
+        let result = context
+            .ethereum_call(&GasCounter::new(), call_ptr)
+            .expect("Couldn't call ethereum_call.");
+        if result.is_null() {
+            return None;
+        }
+        Some(
+            asc_get::<_, Array<AscPtr<AscEnum<EthereumValueKind>>>, _>(
+                &context.wasm_ctx,
+                result,
+                &GasCounter::new(),
+            )
+            .expect("Couldn't unwrap result."),
+        )
+    }
+
+    fn mock_function_matching(
+        context: &mut MatchstickInstanceContext<Chain>,
+        contract_address: &str,
+        fn_name: &str,
+        fn_signature: &str,
+        arg_matchers: &[&str],
+        return_value: &[Token],
+    ) {
+        let contract_address_ptr = string_ptr(context, contract_address);
+        let fn_name_ptr = string_ptr(context, fn_name);
+        let fn_signature_ptr = string_ptr(context, fn_signature);
+        let arg_matcher_ptrs: Vec<AscPtr<AscString>> = arg_matchers
+            .iter()
+            .map(|matcher| string_ptr(context, matcher))
+            .collect();
+        let arg_matchers_ptr = AscPtr::alloc_obj(
+            Array::new(&arg_matcher_ptrs, &mut context.wasm_ctx, &GasCounter::new())
+                .expect("Couldn't create array."),
+            &mut context.wasm_ctx,
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create pointer.");
+        let return_value_ptr = asc_new(&mut context.wasm_ctx, return_value, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+
+        context
+            .mock_function_matching(
+                &GasCounter::new(),
+                contract_address_ptr,
+                fn_name_ptr,
+                fn_signature_ptr,
+                arg_matchers_ptr,
+                return_value_ptr.wasm_ptr(),
+                AscPtr::new(0),
+            )
+            .expect("Couldn't call mock_function_matching.");
+    }
+
+    fn contract_called(
+        context: &mut MatchstickInstanceContext<Chain>,
+        contract_address: &str,
+        fn_signature: &str,
+        arg_matchers: &[&str],
+        times: u32,
+    ) -> bool {
+        let contract_address_ptr = string_ptr(context, contract_address);
+        let fn_signature_ptr = string_ptr(context, fn_signature);
+        let arg_matcher_ptrs: Vec<AscPtr<AscString>> = arg_matchers
+            .iter()
+            .map(|matcher| string_ptr(context, matcher))
+            .collect();
+        let arg_matchers_ptr = AscPtr::alloc_obj(
+            Array::new(&arg_matcher_ptrs, &mut context.wasm_ctx, &GasCounter::new())
+                .expect("Couldn't create array."),
+            &mut context.wasm_ctx,
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create pointer.");
+
+        context
+            .assert_contract_called(
+                &GasCounter::new(),
+                contract_address_ptr,
+                fn_signature_ptr,
+                arg_matchers_ptr,
+                times,
+            )
+            .expect("Couldn't call assert_contract_called.")
+    }
+
+    /// Creates the pointers passed to the `mockFunction` host functions, other than `reverts`.
+    fn mocked_function_ptrs(
+        context: &mut MatchstickInstanceContext<Chain>,
+        contract_address: &str,
+        fn_name: &str,
+        fn_signature: &str,
+        fn_args: &[Token],
+        return_value: &[Token],
+    ) -> (u32, AscPtr<AscString>, AscPtr<AscString>, u32, u32) {
+        let contract_address =
+            Address::from_str(contract_address).expect("Couldn't create Address.");
+        let contract_address = TypedArray::new(
+            contract_address.as_bytes(),
+            &mut context.wasm_ctx,
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create address.");
+        let contract_address_ptr =
+            AscPtr::alloc_obj(contract_address, &mut context.wasm_ctx, &GasCounter::new())
+                .expect("Couldn't create pointer.");
+        let fn_args_ptr = asc_new(&mut context.wasm_ctx, fn_args, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+        let return_value_ptr = asc_new(&mut context.wasm_ctx, return_value, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+
+        (
+            contract_address_ptr.wasm_ptr(),
+            string_ptr(context, fn_name),
+            string_ptr(context, fn_signature),
+            fn_args_ptr.wasm_ptr(),
+            return_value_ptr.wasm_ptr(),
+        )
+    }
+
+    fn use_gravity_abi() {
+        ABI_FILES.with(|files| {
+            *files.borrow_mut() = HashMap::from([(
+                "Gravity".to_owned(),
+                PathBuf::from("./mocks/abis/Gravity.json"),
+            )])
+        });
+    }
+
+    /// Answers `eth_call` requests like a node would: `balanceOf` returns 42,
+    /// anything else reverts with "Not the owner".
+    fn serve_eth_calls(listener: TcpListener, requests: usize) {
+        for stream in listener.incoming().take(requests) {
+            let mut stream = stream.expect("Couldn't accept connection.");
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+            loop {
+                let read = stream.read(&mut buffer).expect("Couldn't read request.");
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request);
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .filter_map(|line| line.split_once(':'))
+                        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                        .and_then(|(_, length)| length.trim().parse::<usize>().ok())
+                        .unwrap_or_default();
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+
+            let response = if String::from_utf8_lossy(&request).contains("0x70a08231") {
+                format!(r#"{{"jsonrpc":"2.0","id":1,"result":"0x{:064x}"}}"#, 42)
+            } else {
+                let mut data = vec![0x08, 0xc3, 0x79, 0xa0];
+                data.extend(ethabi::encode(&[Token::String("Not the owner".to_owned())]));
+                let data: String = data.iter().map(|byte| format!("{:02x}", byte)).collect();
+                format!(
+                    r#"{{"jsonrpc":"2.0","id":1,"error":{{"code":3,"message":"execution reverted: Not the owner","data":"0x{}"}}}}"#,
+                    data
+                )
+            };
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.len(),
+                response
+            )
+            .expect("Couldn't write response.");
+        }
+    }
+
     #[test]
     #[serial]
     fn log_basic_test() {
@@ -80,13 +364,13 @@ mod unit_tests {
     fn clear_store_basic_test() {
         let mut context = get_context();
 
-        context.store.insert("type".to_owned(), HashMap::new());
+        context.entities().insert("type".to_owned(), HashMap::new());
 
         context
             .clear_store(&GasCounter::new())
             .expect("Couldn't call clear_store");
 
-        assert_eq!(context.store.len(), 0);
+        assert_eq!(context.entities().len(), 0);
     }
 
     #[test]
@@ -138,9 +422,11 @@ mod unit_tests {
         )
         .expect("Couldn't create pointer.");
 
-        context.store.insert("entity".to_owned(), HashMap::new());
+        context
+            .entities()
+            .insert("entity".to_owned(), HashMap::new());
         let mut inner_map = context
-            .store
+            .entities()
             .get("entity")
             .expect("Couldn't get inner map.")
             .clone();
@@ -151,7 +437,7 @@ mod unit_tests {
             .clone();
         entity.insert("field_name".to_owned(), Value::String("val".to_owned()));
         inner_map.insert("id".to_owned(), entity);
-        context.store.insert("entity".to_owned(), inner_map);
+        context.entities().insert("entity".to_owned(), inner_map);
 
         let result = context
             .assert_field_equals(
@@ -202,7 +488,9 @@ mod unit_tests {
 
         assert!(!result);
 
-        context.store.insert("entity".to_owned(), HashMap::new());
+        context
+            .entities()
+            .insert("entity".to_owned(), HashMap::new());
 
         result = context
             .assert_field_equals(
@@ -217,12 +505,12 @@ mod unit_tests {
         assert!(!result);
 
         let mut inner_map = context
-            .store
+            .entities()
             .get("entity")
             .expect("Couldn't get inner map.")
             .clone();
         inner_map.insert("id".to_owned(), HashMap::new());
-        context.store.insert("entity".to_owned(), inner_map);
+        context.entities().insert("entity".to_owned(), inner_map);
 
         result = context
             .assert_field_equals(
@@ -237,7 +525,7 @@ mod unit_tests {
         assert!(!result);
 
         let mut inner_map = context
-            .store
+            .entities()
             .get("entity")
             .expect("Couldn't get inner map.")
             .clone();
@@ -247,7 +535,7 @@ mod unit_tests {
             .clone();
         entity.insert("field_name".to_owned(), Value::Null);
         inner_map.insert("id".to_owned(), entity);
-        context.store.insert("entity".to_owned(), inner_map);
+        context.entities().insert("entity".to_owned(), inner_map);
 
         result = context
             .assert_field_equals(
@@ -262,6 +550,164 @@ mod unit_tests {
         assert!(!result);
     }
 
+    #[test]
+    #[serial]
+    fn assert_field_equals_by_schema_type() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/delegators.json"));
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/gravatars.yaml"));
+
+        assert!(field_equals(
+            &mut context,
+            "Delegator",
+            "0xdelegator",
+            "totalRealizedRewards",
+            "1.50"
+        ));
+        assert!(field_equals(
+            &mut context,
+            "Delegator",
+            "0xdelegator",
+            "totalStakedTokens",
+            "1000000000000000000"
+        ));
+        assert!(field_equals(
+            &mut context,
+            "Gravatar",
+            "0x1",
+            "owner",
+            "0x89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7"
+        ));
+        assert!(field_equals(
+            &mut context,
+            "GraphAccount",
+            "0xaccount",
+            "operators",
+            "[0xoperator]"
+        ));
+        assert!(!field_equals(
+            &mut context,
+            "Delegator",
+            "0xdelegator",
+            "totalRealizedRewards",
+            "1.6"
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn assert_field_equals_with_tolerance() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/delegators.json"));
+
+        let mut within_tolerance = |expected_val: &str, tolerance: &str| {
+            let entity_type_ptr = string_ptr(&mut context, "Delegator");
+            let id_ptr = string_ptr(&mut context, "0xdelegator");
+            let field_name_ptr = string_ptr(&mut context, "totalRealizedRewards");
+            let expected_val_ptr = string_ptr(&mut context, expected_val);
+            let tolerance_ptr = string_ptr(&mut context, tolerance);
+
+            context
+                .assert_field_equals_with_tolerance(
+                    &GasCounter::new(),
+                    entity_type_ptr,
+                    id_ptr,
+                    field_name_ptr,
+                    expected_val_ptr,
+                    tolerance_ptr,
+                )
+                .expect("Couldn't call assert_field_equals_with_tolerance.")
+        };
+
+        assert!(within_tolerance("1.4", "0.2"));
+        assert!(!within_tolerance("1.2", "0.2"));
+    }
+
+    #[test]
+    #[serial]
+    fn assert_entity_equals_basic_test() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/delegators.json"));
+        let mut expected = context
+            .entities()
+            .get("Delegator")
+            .and_then(|delegators| delegators.get("0xdelegator"))
+            .expect("No such key in map")
+            .clone();
+        // Equal by value, although written differently.
+        expected.insert(
+            "totalRealizedRewards".to_owned(),
+            Value::BigDecimal(BigDecimal::from_str("1.50").unwrap()),
+        );
+        expected.remove("defaultDisplayName");
+
+        let entity_type = asc_string_from_str("Delegator");
+        let id = asc_string_from_str("0xdelegator");
+        let entity_type_ptr =
+            AscPtr::alloc_obj(entity_type, &mut context.wasm_ctx, &GasCounter::new())
+                .expect("Couldn't create pointer.");
+        let id_ptr = AscPtr::alloc_obj(id, &mut context.wasm_ctx, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+        let expected_ptr = asc_new(
+            &mut context.wasm_ctx,
+            &Entity::from(expected).sorted(),
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create pointer.");
+
+        let result = context
+            .assert_entity_equals(&GasCounter::new(), entity_type_ptr, id_ptr, expected_ptr)
+            .expect("Couldn't call assert_entity_equals.");
+
+        assert!(result);
+    }
+
+    #[test]
+    #[serial]
+    fn assert_entity_equals_reports_field_diff() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/gravatars.yaml"));
+        let mut expected = context
+            .entities()
+            .get("Gravatar")
+            .and_then(|gravatars| gravatars.get("0x1"))
+            .expect("No such key in map")
+            .clone();
+        expected.insert("displayName".to_owned(), Value::from("Other Gravatar"));
+        expected.remove("imageUrl");
+        expected.insert("nickname".to_owned(), Value::from("first"));
+
+        let entity_type = asc_string_from_str("Gravatar");
+        let id = asc_string_from_str("0x1");
+        let entity_type_ptr =
+            AscPtr::alloc_obj(entity_type, &mut context.wasm_ctx, &GasCounter::new())
+                .expect("Couldn't create pointer.");
+        let id_ptr = AscPtr::alloc_obj(id, &mut context.wasm_ctx, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+        let expected_ptr = asc_new(
+            &mut context.wasm_ctx,
+            &Entity::from(expected).sorted(),
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create pointer.");
+
+        accum();
+        let result = context
+            .assert_entity_equals(&GasCounter::new(), entity_type_ptr, id_ptr, expected_ptr)
+            .expect("Couldn't call assert_entity_equals.");
+        let logs = flush();
+
+        assert!(!result);
+        assert!(logs
+            .contains("field 'displayName' expected 'Other Gravatar', but was 'First Gravatar'"));
+        assert!(logs.contains("extra field 'imageUrl'"));
+        assert!(logs.contains("missing field 'nickname'"));
+    }
+
     #[test]
     #[serial]
     fn assert_equals_basic_test() {
@@ -349,15 +795,17 @@ mod unit_tests {
         let mut context = get_context();
 
         context
-            .store
+            .entities()
             .insert("entity_type".to_owned(), HashMap::new());
         let mut inner_map = context
-            .store
+            .entities()
             .get("entity_type")
             .expect("Couldn't get inner map.")
             .clone();
         inner_map.insert("id".to_owned(), HashMap::new());
-        context.store.insert("entity_type".to_owned(), inner_map);
+        context
+            .entities()
+            .insert("entity_type".to_owned(), inner_map);
 
         let entity_type = asc_string_from_str("entity_type");
         let id = asc_string_from_str("id");
@@ -375,14 +823,85 @@ mod unit_tests {
         assert!(!result);
     }
 
+    #[test]
+    #[serial]
+    fn assert_store_matches_snapshot_basic_test() {
+        let mut context = get_context();
+
+        let tests_location = std::env::temp_dir().join("matchstick_snapshot_tests");
+        let _ = std::fs::remove_dir_all(&tests_location);
+        TESTS_LOCATION.with(|path| *path.borrow_mut() = tests_location.clone());
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/gravatars.yaml"));
+
+        let name = asc_string_from_str("gravatars");
+        let name_ptr = AscPtr::alloc_obj(name, &mut context.wasm_ctx, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+
+        // The first run writes the snapshot.
+        let mut result = context
+            .assert_store_matches_snapshot(&GasCounter::new(), name_ptr)
+            .expect("Couldn't call assert_store_matches_snapshot.");
+        assert!(result);
+        assert!(tests_location.join("__snapshots__/gravatars.json").exists());
+
+        result = context
+            .assert_store_matches_snapshot(&GasCounter::new(), name_ptr)
+            .expect("Couldn't call assert_store_matches_snapshot.");
+        assert!(result);
+
+        context
+            .entities()
+            .get_mut("Gravatar")
+            .and_then(|gravatars| gravatars.get_mut("0x1"))
+            .expect("No such key in map")
+            .insert("displayName".to_owned(), Value::from("Renamed Gravatar"));
+
+        accum();
+        result = context
+            .assert_store_matches_snapshot(&GasCounter::new(), name_ptr)
+            .expect("Couldn't call assert_store_matches_snapshot.");
+        let logs = flush();
+        assert!(!result);
+        assert!(logs.contains("Gravatar[0x1].displayName"));
+
+        UPDATE_SNAPSHOTS.with(|update| *update.borrow_mut() = true);
+        result = context
+            .assert_store_matches_snapshot(&GasCounter::new(), name_ptr)
+            .expect("Couldn't call assert_store_matches_snapshot.");
+        UPDATE_SNAPSHOTS.with(|update| *update.borrow_mut() = false);
+        assert!(result);
+
+        result = context
+            .assert_store_matches_snapshot(&GasCounter::new(), name_ptr)
+            .expect("Couldn't call assert_store_matches_snapshot.");
+        assert!(result);
+
+        // Dots in the name are kept, not treated as an extension.
+        let dotted = asc_string_from_str("gravatars.after_update");
+        let dotted_ptr = AscPtr::alloc_obj(dotted, &mut context.wasm_ctx, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+        result = context
+            .assert_store_matches_snapshot(&GasCounter::new(), dotted_ptr)
+            .expect("Couldn't call assert_store_matches_snapshot.");
+        assert!(result);
+        assert!(tests_location
+            .join("__snapshots__/gravatars.after_update.json")
+            .exists());
+
+        TESTS_LOCATION.with(|path| *path.borrow_mut() = PathBuf::new());
+    }
+
     #[test]
     #[serial]
     fn mock_store_get_basic_test() {
         let mut context = get_context();
 
-        context.store.insert("entity".to_owned(), HashMap::new());
+        context
+            .entities()
+            .insert("entity".to_owned(), HashMap::new());
         let mut inner_map = context
-            .store
+            .entities()
             .get("entity")
             .expect("Couldn't get inner map.")
             .clone();
@@ -393,7 +912,7 @@ mod unit_tests {
             .clone();
         entity.insert("field_name".to_owned(), Value::String("val".to_owned()));
         inner_map.insert("id".to_owned(), entity);
-        context.store.insert("entity".to_owned(), inner_map);
+        context.entities().insert("entity".to_owned(), inner_map);
 
         let entity = asc_string_from_str("entity");
         let id = asc_string_from_str("id");
@@ -498,9 +1017,10 @@ mod unit_tests {
             .expect("Couldn't call mock_store_get.");
 
         let inner_map = context
-            .store
+            .entities()
             .get("entity")
-            .expect("Couldn't get inner map.");
+            .expect("Couldn't get inner map.")
+            .clone();
         assert_eq!(inner_map.len(), 1);
     }
 
@@ -522,14 +1042,16 @@ mod unit_tests {
         let data_pointer = AscPtr::alloc_obj(data, &mut context.wasm_ctx, &GasCounter::new())
             .expect("Couldn't create pointer.");
 
-        context.store.insert("entity".to_owned(), HashMap::new());
+        context
+            .entities()
+            .insert("entity".to_owned(), HashMap::new());
         let mut inner_map = context
-            .store
+            .entities()
             .get("entity")
             .expect("Couldn't get inner map.")
             .clone();
         inner_map.insert("another_id".to_owned(), HashMap::new());
-        context.store.insert("entity".to_owned(), inner_map);
+        context.entities().insert("entity".to_owned(), inner_map);
 
         let payload = AscEnum::<StoreValueKind> {
             kind: StoreValueKind::String,
@@ -571,9 +1093,10 @@ mod unit_tests {
             .expect("Couldn't call mock_store_get.");
 
         let inner_map = context
-            .store
+            .entities()
             .get("entity")
-            .expect("Couldn't get inner map.");
+            .expect("Couldn't get inner map.")
+            .clone();
         assert_eq!(inner_map.len(), 2);
     }
 
@@ -596,22 +1119,17 @@ mod unit_tests {
             .expect("Couldn't create pointer.");
 
         context
-            .store
+            .entities()
             .insert("GraphAccount".to_owned(), HashMap::new());
         let mut inner_map = context
-            .store
+            .entities()
             .get("GraphAccount")
             .expect("Couldn't get inner map.")
             .clone();
         inner_map.insert("graphAccountId".to_owned(), HashMap::new());
-        context.store.insert("GraphAccount".to_owned(), inner_map);
-        context.derived.insert(
-            "NameSignalTransaction".to_owned(),
-            (
-                "GraphAccount".to_owned(),
-                vec![("nameSignalTransactions".to_owned(), "signer".to_owned())],
-            ),
-        );
+        context
+            .entities()
+            .insert("GraphAccount".to_owned(), inner_map);
 
         let payload = AscEnum::<StoreValueKind> {
             kind: StoreValueKind::String,
@@ -652,21 +1170,61 @@ mod unit_tests {
             )
             .expect("Couldn't call mock_store_get.");
 
-        let inner_map = context
-            .store
+        let graph_account = context
+            .entities()
             .get("GraphAccount")
             .expect("Couldn't get inner map.")
             .get("graphAccountId")
-            .unwrap();
+            .unwrap()
+            .clone();
+        assert!(!graph_account.contains_key("nameSignalTransactions"));
+
+        let graph_account =
+            context.with_derived_fields("GraphAccount", "graphAccountId", &graph_account);
         assert_eq!(
-            inner_map
-                .get("nameSignalTransactions")
-                .unwrap()
-                .clone()
-                .as_list()
-                .unwrap()
-                .len(),
-            1
+            graph_account.get("nameSignalTransactions"),
+            Some(&Value::List(vec![Value::String("nstid".to_owned())]))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn mock_store_remove_derived_fields() {
+        let mut context = get_context();
+
+        let mut graph_accounts = HashMap::new();
+        graph_accounts.insert("graphAccountId".to_owned(), HashMap::new());
+        context
+            .entities()
+            .insert("GraphAccount".to_owned(), graph_accounts);
+
+        let mut nst = HashMap::new();
+        nst.insert(
+            "signer".to_owned(),
+            Value::String("graphAccountId".to_owned()),
+        );
+        let mut transactions = HashMap::new();
+        transactions.insert("nstid".to_owned(), nst);
+        context
+            .entities()
+            .insert("NameSignalTransaction".to_owned(), transactions);
+
+        let entity = asc_string_from_str("NameSignalTransaction");
+        let id = asc_string_from_str("nstid");
+        let entity_pointer = AscPtr::alloc_obj(entity, &mut context.wasm_ctx, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+        let id_pointer = AscPtr::alloc_obj(id, &mut context.wasm_ctx, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+
+        context
+            .mock_store_remove(&GasCounter::new(), entity_pointer, id_pointer)
+            .expect("Couldn't call mock_store_remove.");
+
+        let graph_account =
+            context.with_derived_fields("GraphAccount", "graphAccountId", &HashMap::new());
+        assert_eq!(
+            graph_account.get("nameSignalTransactions"),
+            Some(&Value::List(vec![]))
         );
     }
 
@@ -675,14 +1233,16 @@ mod unit_tests {
     fn mock_store_remove_basic_test() {
         let mut context = get_context();
 
-        context.store.insert("entity".to_owned(), HashMap::new());
+        context
+            .entities()
+            .insert("entity".to_owned(), HashMap::new());
         let mut inner_map = context
-            .store
+            .entities()
             .get("entity")
             .expect("Couldn't get inner map.")
             .clone();
         inner_map.insert("id".to_owned(), HashMap::new());
-        context.store.insert("entity".to_owned(), inner_map);
+        context.entities().insert("entity".to_owned(), inner_map);
 
         let entity = asc_string_from_str("entity");
         let id = asc_string_from_str("id");
@@ -695,7 +1255,7 @@ mod unit_tests {
             .mock_store_remove(&GasCounter::new(), entity_pointer, id_pointer)
             .expect("Couldn't call mock_store_remove.");
 
-        assert!(!context.store.get("entity").unwrap().contains_key("id"));
+        assert!(!context.entities().get("entity").unwrap().contains_key("id"));
     }
 
     #[test]
@@ -705,7 +1265,7 @@ mod unit_tests {
 
         context.fn_ret_map.insert(
             "0x8920…43e7funcNamefuncName(address):(string,string)val".to_owned(),
-            vec![Token::Bool(false)],
+            CallOutcome::Returned(vec![Token::Bool(false)]),
         );
 
         let contract_name = asc_string_from_str("contractName");
@@ -788,7 +1348,7 @@ mod unit_tests {
 
         context.fn_ret_map.insert(
             "0x8920…43e7funcNamefuncName(address):(string,string)val".to_owned(),
-            REVERTS_IDENTIFIER.clone(),
+            CallOutcome::Reverted(None),
         );
 
         let contract_name = asc_string_from_str("contractName");
@@ -920,11 +1480,13 @@ mod unit_tests {
 
         println!("{:?}", context.fn_ret_map);
 
-        let token = context
+        let token = match context
             .fn_ret_map
             .get("0x8920…43e7funcNamefuncName(address):(string,string)89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7")
-            .unwrap()[0]
-            .clone();
+        {
+            Some(CallOutcome::Returned(tokens)) => tokens[0].clone(),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        };
         assert_eq!(
             &token.to_string(),
             "89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7"
@@ -991,12 +1553,12 @@ mod unit_tests {
             )
             .expect("Couldn't call mock_function.");
 
-        let token = context
-            .fn_ret_map
-            .get("0x8920…43e7funcNamefuncName(address):(string,string)89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7")
-            .unwrap()[0]
-            .clone();
-        assert_eq!(token, REVERTS_IDENTIFIER[0]);
+        assert_eq!(
+            context
+                .fn_ret_map
+                .get("0x8920…43e7funcNamefuncName(address):(string,string)89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7"),
+            Some(&CallOutcome::Reverted(None))
+        );
     }
 
     #[test]
@@ -1126,7 +1688,9 @@ mod unit_tests {
         let mut gravatar_map = HashMap::new();
         gravatar_map.insert("gravatar1".to_owned(), HashMap::new());
         gravatar_map.insert("gravatar2".to_owned(), HashMap::new());
-        context.store.insert("gravatar".to_owned(), gravatar_map);
+        context
+            .entities()
+            .insert("gravatar".to_owned(), gravatar_map);
 
         result = context
             .count_entities(&GasCounter::new(), gravatar_ptr)
@@ -1137,15 +1701,1691 @@ mod unit_tests {
 
     #[test]
     #[serial]
-    fn mock_ipfs_file_basic_test() {
+    fn query_entities_with_filters_and_ordering() {
         let mut context = get_context();
 
-        assert_eq!(context.ipfs.len(), 0);
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/gravatars.yaml"));
 
-        let hash = asc_string_from_str("QmTkzDwWqPbnAh5YiV5VwcTLnGdwSNsNTn2aDxdXBFca7D");
-        let file = asc_string_from_str("./mocks/ipfs.json");
-        let hash_ptr = AscPtr::alloc_obj(hash, &mut context.wasm_ctx, &GasCounter::new())
-            .expect("Couldn't create pointer.");
+        let entity_type_ptr = string_ptr(&mut context, "Gravatar");
+        let query_ptr = string_ptr(
+            &mut context,
+            r#"{"where": {"displayName_in": ["First Gravatar", "Second Gravatar"]}, "orderBy": "displayName", "orderDirection": "desc", "first": 1}"#,
+        );
+
+        let result = context
+            .query_entities(&GasCounter::new(), entity_type_ptr, query_ptr)
+            .expect("Couldn't call query_entities.");
+        let ids: Vec<String> =
+            asc_get(&context.wasm_ctx, result, &GasCounter::new()).expect("Couldn't get ids.");
+
+        assert_eq!(ids, vec!["0x2".to_owned()]);
+    }
+
+    #[test]
+    #[serial]
+    fn assert_entity_count_and_entities_where() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/delegators.json"));
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/gravatars.yaml"));
+
+        let gravatar_ptr = string_ptr(&mut context, "Gravatar");
+        let result = context
+            .assert_entity_count(&GasCounter::new(), gravatar_ptr, 2)
+            .expect("Couldn't call assert_entity_count.");
+        assert!(result);
+
+        let result = context
+            .assert_entity_count(&GasCounter::new(), gravatar_ptr, 3)
+            .expect("Couldn't call assert_entity_count.");
+        assert!(!result);
+
+        let delegator_ptr = string_ptr(&mut context, "Delegator");
+        let filter_ptr = string_ptr(
+            &mut context,
+            r#"{"totalStakedTokens_gt": "999999999999999999", "totalRealizedRewards_lte": 1.5}"#,
+        );
+        let result = context
+            .assert_entities_where(&GasCounter::new(), delegator_ptr, filter_ptr, 1)
+            .expect("Couldn't call assert_entities_where.");
+        assert!(result);
+
+        let filter_ptr = string_ptr(&mut context, r#"{"displayName_not": "First Gravatar"}"#);
+        let result = context
+            .assert_entities_where(&GasCounter::new(), gravatar_ptr, filter_ptr, 2)
+            .expect("Couldn't call assert_entities_where.");
+        assert!(!result);
+    }
+
+    #[test]
+    #[serial]
+    #[should_panic(expected = "Expected count should not be negative, got -1.")]
+    fn negative_entity_counts_are_rejected() {
+        let mut context = get_context();
+
+        let gravatar_ptr = string_ptr(&mut context, "Gravatar");
+        context
+            .assert_entity_count(&GasCounter::new(), gravatar_ptr, -1i32 as u32)
+            .expect("Couldn't call assert_entity_count.");
+    }
+
+    #[test]
+    #[serial]
+    fn query_graphql_nested_and_derived_fields() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/delegators.json"));
+        let mut operator = HashMap::new();
+        operator.insert("id".to_owned(), Value::String("0xoperator".to_owned()));
+        context
+            .entities()
+            .get_mut("GraphAccount")
+            .expect("No such key in map")
+            .insert("0xoperator".to_owned(), operator);
+
+        let response = context.execute_graphql(
+            r#"
+            query Delegators($min: BigInt) {
+                delegators(where: { totalStakedTokens_gte: $min }, orderBy: createdAt) {
+                    id
+                    totalRealizedRewards
+                    account { id createdAt operators }
+                }
+                operator: graphAccount(id: "0xoperator") {
+                    ...Operator
+                }
+            }
+
+            fragment Operator on GraphAccount {
+                operatorOf { id }
+            }
+            "#,
+            &serde_json::json!({ "min": "1000000000000000000" }),
+        );
+
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "data": {
+                    "delegators": [{
+                        "id": "0xdelegator",
+                        "totalRealizedRewards": "1.5",
+                        "account": { "id": "0xaccount", "createdAt": 1, "operators": ["0xoperator"] }
+                    }],
+                    "operator": { "operatorOf": [{ "id": "0xaccount" }] }
+                }
+            })
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn query_graphql_root_fields_of_the_api_schema() {
+        let context = get_context();
+
+        let response = context.execute_graphql(
+            "{ entities { id } subgraphCategories { id } graphNetworks { id } accounts { id } transfers { id } }",
+            &serde_json::Value::Null,
+        );
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "data": {
+                    "entities": [],
+                    "subgraphCategories": [],
+                    "graphNetworks": [],
+                    "accounts": [],
+                    "transfers": []
+                }
+            })
+        );
+
+        let response =
+            context.execute_graphql("{ subgraphCategorys { id } }", &serde_json::Value::Null);
+        assert!(response.get("errors").is_some());
+    }
+
+    #[test]
+    #[serial]
+    fn assert_graphql_result_equals_reports_errors() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/gravatars.yaml"));
+
+        let query_ptr = string_ptr(
+            &mut context,
+            "{ gravatars(orderBy: displayName, orderDirection: desc) { displayName } }",
+        );
+        let variables_ptr = string_ptr(&mut context, "");
+        let expected_ptr = string_ptr(
+            &mut context,
+            r#"{"gravatars": [{"displayName": "Second Gravatar"}, {"displayName": "First Gravatar"}]}"#,
+        );
+        let result = context
+            .assert_graphql_result_equals(
+                &GasCounter::new(),
+                query_ptr,
+                variables_ptr,
+                expected_ptr,
+            )
+            .expect("Couldn't call assert_graphql_result_equals.");
+        assert!(result);
+
+        let query_ptr = string_ptr(&mut context, "{ gravatars { nickname } }");
+        let result = context
+            .assert_graphql_result_equals(
+                &GasCounter::new(),
+                query_ptr,
+                variables_ptr,
+                expected_ptr,
+            )
+            .expect("Couldn't call assert_graphql_result_equals.");
+        assert!(!result);
+    }
+
+    #[test]
+    #[serial]
+    fn entity_history_by_block() {
+        let mut context = get_context();
+
+        let gravatar_ptr = string_ptr(&mut context, "Gravatar");
+        let id_ptr = string_ptr(&mut context, "0x1");
+        let set_display_name = |context: &mut MatchstickInstanceContext<Chain>,
+                                block_number: u32,
+                                display_name: &str| {
+            let event_ptr = string_ptr(context, "NewGravatar");
+            context
+                .mock_block(&GasCounter::new(), block_number, event_ptr)
+                .expect("Couldn't call mock_block.");
+
+            let mut data = HashMap::new();
+            data.insert("id".to_owned(), Value::String("0x1".to_owned()));
+            data.insert(
+                "displayName".to_owned(),
+                Value::String(display_name.to_owned()),
+            );
+            let data_ptr = asc_new(
+                &mut context.wasm_ctx,
+                &Entity::from(data).sorted(),
+                &GasCounter::new(),
+            )
+            .expect("Couldn't create pointer.");
+            context
+                .mock_store_set(&GasCounter::new(), gravatar_ptr, id_ptr, data_ptr)
+                .expect("Couldn't call mock_store_set.");
+        };
+
+        set_display_name(&mut context, 1, "First");
+        set_display_name(&mut context, 2, "Second");
+        context.block.number = 3;
+        context
+            .mock_store_remove(&GasCounter::new(), gravatar_ptr, id_ptr)
+            .expect("Couldn't call mock_store_remove.");
+
+        let display_name_at = |block_number| {
+            context
+                .entity_at_block("Gravatar", "0x1", block_number)
+                .and_then(|entity| entity.get("displayName").cloned())
+        };
+        assert_eq!(display_name_at(0), None);
+        assert_eq!(display_name_at(1), Some(Value::from("First")));
+        assert_eq!(display_name_at(2), Some(Value::from("Second")));
+        assert_eq!(display_name_at(3), None);
+        assert_eq!(
+            context.store.history()[1].block.event,
+            Some("NewGravatar".to_owned())
+        );
+
+        let result = context
+            .assert_entity_change_count(&GasCounter::new(), gravatar_ptr, id_ptr, 3)
+            .expect("Couldn't call assert_entity_change_count.");
+        assert!(result);
+
+        let field_name_ptr = string_ptr(&mut context, "displayName");
+        let expected: Vec<String> =
+            vec!["First".to_owned(), "Second".to_owned(), "null".to_owned()];
+        let expected_ptr = asc_new(
+            &mut context.wasm_ctx,
+            expected.as_slice(),
+            &GasCounter::new(),
+        )
+        .expect("Couldn't create pointer.");
+        let result = context
+            .assert_field_history(
+                &GasCounter::new(),
+                gravatar_ptr,
+                id_ptr,
+                field_name_ptr,
+                expected_ptr,
+            )
+            .expect("Couldn't call assert_field_history.");
+        assert!(result);
+    }
+
+    #[test]
+    #[serial]
+    #[should_panic(expected = "Expected count should not be negative, got -2.")]
+    fn negative_entity_change_counts_are_rejected() {
+        let mut context = get_context();
+
+        let gravatar_ptr = string_ptr(&mut context, "Gravatar");
+        let id_ptr = string_ptr(&mut context, "0x1");
+        context
+            .assert_entity_change_count(&GasCounter::new(), gravatar_ptr, id_ptr, -2i32 as u32)
+            .expect("Couldn't call assert_entity_change_count.");
+    }
+
+    #[test]
+    #[serial]
+    fn revert_to_block_undoes_later_changes() {
+        let mut context = get_context();
+        let gravatar = |id: &str, display_name: &str| {
+            let mut data = HashMap::new();
+            data.insert("id".to_owned(), Value::from(id));
+            data.insert("displayName".to_owned(), Value::from(display_name));
+            data
+        };
+
+        context.block.number = 1;
+        store_set(&mut context, "Gravatar", "0x1", gravatar("0x1", "First"));
+
+        context.block.number = 2;
+        store_set(&mut context, "Gravatar", "0x1", gravatar("0x1", "Second"));
+        store_set(&mut context, "Gravatar", "0x2", gravatar("0x2", "Other"));
+        let name_ptr = string_ptr(&mut context, "Gravity");
+        let params: Vec<String> = vec!["0x89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7".to_owned()];
+        let params_ptr = asc_new(&mut context.wasm_ctx, params.as_slice(), &GasCounter::new())
+            .expect("Couldn't create pointer.");
+        context
+            .mock_data_source_create(&GasCounter::new(), name_ptr, params_ptr)
+            .expect("Couldn't call mock_data_source_create.");
+        assert_eq!(context.data_sources.len(), 1);
+
+        context
+            .revert_to_block(&GasCounter::new(), 1)
+            .expect("Couldn't call revert_to_block.");
+
+        let gravatars = context
+            .entities()
+            .get("Gravatar")
+            .expect("No such key in map")
+            .clone();
+        assert_eq!(gravatars.len(), 1);
+        assert_eq!(
+            gravatars.get("0x1").and_then(|g| g.get("displayName")),
+            Some(&Value::from("First"))
+        );
+        assert_eq!(context.store.history().len(), 1);
+        assert_eq!(context.block.number, 1);
+
+        let result = context
+            .assert_data_source_count(&GasCounter::new(), name_ptr, 0)
+            .expect("Couldn't call assert_data_source_count.");
+        assert!(result);
+    }
+
+    #[test]
+    #[serial]
+    fn revert_to_block_with_blocks_out_of_order() {
+        let mut context = get_context();
+        let gravatar = |display_name: &str| {
+            let mut data = HashMap::new();
+            data.insert("id".to_owned(), Value::from("0x1"));
+            data.insert("displayName".to_owned(), Value::from(display_name));
+            data
+        };
+        let display_name = |context: &MatchstickInstanceContext<Chain>| {
+            context
+                .entities()
+                .get("Gravatar")
+                .and_then(|gravatars| gravatars.get("0x1"))
+                .and_then(|gravatar| gravatar.get("displayName").cloned())
+        };
+
+        context.block.number = 1;
+        store_set(&mut context, "Gravatar", "0x1", gravatar("First"));
+        context.block.number = 5;
+        store_set(&mut context, "Gravatar", "0x1", gravatar("Fifth"));
+        context.block.number = 3;
+        store_set(&mut context, "Gravatar", "0x1", gravatar("Third"));
+        context.block.number = 7;
+        store_set(&mut context, "Gravatar", "0x1", gravatar("Seventh"));
+
+        context
+            .revert_to_block(&GasCounter::new(), 4)
+            .expect("Couldn't call revert_to_block.");
+        assert_eq!(display_name(&context), Some(Value::from("Third")));
+        assert_eq!(context.store.history().len(), 2);
+        assert_eq!(
+            context.store.history()[1]
+                .previous
+                .as_ref()
+                .and_then(|gravatar| gravatar.get("displayName")),
+            Some(&Value::from("First"))
+        );
+
+        context
+            .revert_to_block(&GasCounter::new(), 0)
+            .expect("Couldn't call revert_to_block.");
+        assert_eq!(display_name(&context), None);
+        assert!(context.store.history().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    #[should_panic(expected = "Block number should not be negative, got -1.")]
+    fn negative_block_numbers_are_rejected() {
+        let mut context = get_context();
+
+        context
+            .revert_to_block(&GasCounter::new(), -1i32 as u32)
+            .expect("Couldn't call revert_to_block.");
+    }
+
+    #[test]
+    #[serial]
+    fn bytes_and_bigint_ids() {
+        let mut context = get_context();
+        let account_id = Bytes::from_str("0x89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7").unwrap();
+
+        let mut account = HashMap::new();
+        account.insert("id".to_owned(), Value::Bytes(account_id.clone()));
+        store_set(
+            &mut context,
+            "Account",
+            "0x89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7",
+            account,
+        );
+
+        let mut transfer = HashMap::new();
+        transfer.insert("id".to_owned(), Value::BigInt(BigInt::from(7)));
+        transfer.insert("from".to_owned(), Value::Bytes(account_id.clone()));
+        transfer.insert("amount".to_owned(), Value::BigInt(BigInt::from(100)));
+        store_set(&mut context, "Transfer", "007", transfer);
+
+        let accounts = context
+            .entities()
+            .get("Account")
+            .expect("No such key in map")
+            .clone();
+        let account = accounts
+            .get("0x89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7")
+            .expect("Bytes ids should be stored as lower case hex.");
+        assert!(context
+            .entities()
+            .get("Transfer")
+            .expect("No such key in map")
+            .contains_key("7"));
+
+        let account = context.with_derived_fields(
+            "Account",
+            "0x89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7",
+            account,
+        );
+        assert_eq!(
+            account.get("transfers"),
+            Some(&Value::List(vec![Value::BigInt(BigInt::from(7))]))
+        );
+
+        // Relations to entities with `Bytes` ids are filtered by `Bytes`, whatever the case.
+        let entity_type_ptr = string_ptr(&mut context, "Transfer");
+        let query_ptr = string_ptr(
+            &mut context,
+            r#"{"where": {"from": "0x89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7"}}"#,
+        );
+        let result = context
+            .query_entities(&GasCounter::new(), entity_type_ptr, query_ptr)
+            .expect("Couldn't call query_entities.");
+        let ids: Vec<String> =
+            asc_get(&context.wasm_ctx, result, &GasCounter::new()).expect("Couldn't get ids.");
+        assert_eq!(ids, vec!["7".to_owned()]);
+
+        let response = context.execute_graphql(
+            r#"{ transfer(id: "7") { amount from { id transfers { id } } } }"#,
+            &serde_json::Value::Null,
+        );
+        assert_eq!(
+            response,
+            serde_json::json!({
+                "data": {
+                    "transfer": {
+                        "amount": "100",
+                        "from": {
+                            "id": "0x89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7",
+                            "transfers": [{ "id": "7" }]
+                        }
+                    }
+                }
+            })
+        );
+
+        let response = context.execute_graphql(
+            r#"{ transfers(where: { from: "0x89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7" }) { id } }"#,
+            &serde_json::Value::Null,
+        );
+        assert_eq!(
+            response,
+            serde_json::json!({ "data": { "transfers": [{ "id": "7" }] } })
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn store_graph_shows_references_and_derived_fields() {
+        let mut context = get_context();
+
+        let mut account = HashMap::new();
+        account.insert(
+            "id".to_owned(),
+            Value::Bytes(Bytes::from_str("0xaa").unwrap()),
+        );
+        store_set(&mut context, "Account", "0xaa", account);
+
+        for (id, from) in [("1", "0xaa"), ("2", "0xbb")] {
+            let mut transfer = HashMap::new();
+            transfer.insert(
+                "id".to_owned(),
+                Value::BigInt(BigInt::from_str(id).unwrap()),
+            );
+            transfer.insert(
+                "from".to_owned(),
+                Value::Bytes(Bytes::from_str(from).unwrap()),
+            );
+            transfer.insert("amount".to_owned(), Value::BigInt(BigInt::from(1)));
+            store_set(&mut context, "Transfer", id, transfer);
+        }
+
+        let format = string_ptr(&mut context, "dot");
+        let graph_ptr = context
+            .store_graph(&GasCounter::new(), format)
+            .expect("Couldn't call store_graph.");
+        let graph: String = asc_get(&context.wasm_ctx, graph_ptr, &GasCounter::new()).unwrap();
+
+        assert_eq!(
+            graph,
+            [
+                "digraph store {",
+                "  node [shape=box];",
+                "  \"Account:0xaa\" [label=\"Account\\n0xaa\"];",
+                "  \"Account:0xbb\" [label=\"Account\\n0xbb\", color=red];",
+                "  \"Transfer:1\" [label=\"Transfer\\n1\"];",
+                "  \"Transfer:2\" [label=\"Transfer\\n2\"];",
+                "  \"Account:0xaa\" -> \"Transfer:1\" [label=\"transfers\", style=dashed];",
+                "  \"Transfer:1\" -> \"Account:0xaa\" [label=\"from\"];",
+                "  \"Transfer:2\" -> \"Account:0xbb\" [label=\"from\"];",
+                "}",
+            ]
+            .join("\n")
+        );
+
+        let format = string_ptr(&mut context, "mermaid");
+        let graph_ptr = context
+            .store_graph(&GasCounter::new(), format)
+            .expect("Couldn't call store_graph.");
+        let graph: String = asc_get(&context.wasm_ctx, graph_ptr, &GasCounter::new()).unwrap();
+
+        assert!(graph.starts_with("graph LR\n"));
+        assert!(graph.contains("  n1[\"Account<br/>0xbb\"]\n  style n1 stroke:red"));
+        assert!(graph.contains("  n0 -.->|transfers| n2"));
+        assert!(graph.contains("  n3 -->|from| n1"));
+    }
+
+    #[test]
+    #[serial]
+    fn mock_function_matching_picks_the_most_specific_mock() {
+        let mut context = get_context();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let signature = "balanceOf(address,uint256):(uint256)";
+        let balance = |n: u64| vec![Token::Uint(n.into())];
+
+        mock_function_matching(
+            &mut context,
+            "*",
+            "balanceOf",
+            signature,
+            &["*", "*"],
+            &balance(1),
+        );
+        mock_function_matching(
+            &mut context,
+            "*",
+            "balanceOf",
+            signature,
+            &["0x8920*", "*"],
+            &balance(2),
+        );
+        mock_function_matching(
+            &mut context,
+            &format!("0x{}", contract),
+            "balanceOf",
+            signature,
+            &["*", "10..20"],
+            &balance(3),
+        );
+
+        let holder = |address: &str| Token::Address(Address::from_str(address).unwrap());
+        let matching_holder = holder("89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7");
+        let other_holder = holder("1111111111111111111111111111111111111111");
+
+        let calls = [
+            (contract, matching_holder, 5u64, 2),
+            (contract, other_holder.clone(), 15, 3),
+            (contract, other_holder.clone(), 30, 1),
+            (
+                "2222222222222222222222222222222222222222",
+                other_holder,
+                15,
+                1,
+            ),
+        ];
+        for (contract_address, holder, amount, expected) in calls {
+            let result = ethereum_call(
+                &mut context,
+                contract_address,
+                "balanceOf",
+                signature,
+                &[holder, Token::Uint(amount.into())],
+            );
+            assert_eq!(result, Some(balance(expected)));
+        }
+
+        // Exact mocks still win over matchers.
+        context.fn_ret_map.insert(
+            "0x8920…43e7balanceOfbalanceOf(address,uint256):(uint256)1111111111111111111111111111111111111111f".to_owned(),
+            CallOutcome::Returned(balance(4)),
+        );
+        let result = ethereum_call(
+            &mut context,
+            contract,
+            "balanceOf",
+            signature,
+            &[
+                holder("1111111111111111111111111111111111111111"),
+                Token::Uint(15u64.into()),
+            ],
+        );
+        assert_eq!(result, Some(balance(4)));
+    }
+
+    #[test]
+    #[serial]
+    fn assert_contract_called_and_no_unexpected_calls() {
+        let mut context = get_context();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let signature = "balanceOf(address):(uint256)";
+        let holder = Token::Address(Address::from_str(contract).unwrap());
+
+        mock_function_matching(
+            &mut context,
+            "*",
+            "balanceOf",
+            signature,
+            &["*"],
+            &[Token::Uint(1u64.into())],
+        );
+        ethereum_call(
+            &mut context,
+            contract,
+            "balanceOf",
+            signature,
+            &[holder.clone()],
+        );
+        ethereum_call(&mut context, contract, "balanceOf", signature, &[holder]);
+
+        assert!(!contract_called(
+            &mut context,
+            contract,
+            signature,
+            &["0x1111*"],
+            1
+        ));
+        assert!(!context
+            .assert_no_unexpected_calls(&GasCounter::new())
+            .unwrap());
+
+        assert!(contract_called(
+            &mut context,
+            "*",
+            signature,
+            &["0x8920*"],
+            2
+        ));
+        assert!(context
+            .assert_no_unexpected_calls(&GasCounter::new())
+            .unwrap());
+        assert_eq!(
+            context.calls[0].result,
+            CallOutcome::Returned(vec![Token::Uint(1u64.into())])
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn mock_function_once_and_at_block() {
+        let mut context = get_context();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let signature = "totalSupply():(uint256)";
+        let supply = |n: u64| vec![Token::Uint(n.into())];
+
+        let (address, name, signature_ptr, args, return_value) = mocked_function_ptrs(
+            &mut context,
+            contract,
+            "totalSupply",
+            signature,
+            &[],
+            &supply(100),
+        );
+        context
+            .mock_function(
+                &GasCounter::new(),
+                address,
+                name,
+                signature_ptr,
+                args,
+                return_value,
+                AscPtr::new(0),
+            )
+            .expect("Couldn't call mock_function.");
+
+        for value in [1, 2] {
+            let (address, name, signature_ptr, args, return_value) = mocked_function_ptrs(
+                &mut context,
+                contract,
+                "totalSupply",
+                signature,
+                &[],
+                &supply(value),
+            );
+            context
+                .mock_function_once(
+                    &GasCounter::new(),
+                    address,
+                    name,
+                    signature_ptr,
+                    args,
+                    return_value,
+                    AscPtr::new(0),
+                )
+                .expect("Couldn't call mock_function_once.");
+        }
+
+        for block_number in [5, 10] {
+            let (address, name, signature_ptr, args, return_value) = mocked_function_ptrs(
+                &mut context,
+                contract,
+                "totalSupply",
+                signature,
+                &[],
+                &supply(block_number as u64 * 100),
+            );
+            context
+                .mock_function_at_block(
+                    &GasCounter::new(),
+                    address,
+                    name,
+                    signature_ptr,
+                    args,
+                    return_value,
+                    AscPtr::new(0),
+                    block_number,
+                )
+                .expect("Couldn't call mock_function_at_block.");
+        }
+
+        for (block_number, expected) in [(0, 1), (0, 2), (0, 100), (7, 500), (10, 1000), (12, 1000)]
+        {
+            context.block.number = block_number;
+            let result = ethereum_call(&mut context, contract, "totalSupply", signature, &[]);
+            assert_eq!(result, Some(supply(expected)));
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn unmocked_call_error_shows_closest_mocks() {
+        let mut context = get_context();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let signature = "balanceOf(address):(uint256)";
+        let holder = |address: &str| Token::Address(Address::from_str(address).unwrap());
+
+        let (address, name, signature_ptr, args, return_value) = mocked_function_ptrs(
+            &mut context,
+            contract,
+            "balanceOf",
+            signature,
+            &[holder("1111111111111111111111111111111111111111")],
+            &[Token::Uint(1u64.into())],
+        );
+        context
+            .mock_function(
+                &GasCounter::new(),
+                address,
+                name,
+                signature_ptr,
+                args,
+                return_value,
+                AscPtr::new(0),
+            )
+            .expect("Couldn't call mock_function.");
+
+        let call_ptr = contract_call_ptr(
+            &mut context,
+            contract,
+            "balanceOf",
+            signature,
+            &[holder("2222222222222222222222222222222222222222")],
+        );
+        let err = match context.ethereum_call(&GasCounter::new(), call_ptr) {
+            Ok(_) => panic!("The call should not be mocked."),
+            Err(err) => err.to_string(),
+        };
+
+        assert!(err.contains(
+            "  0x89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7.balanceOf(address):(uint256) with [0x1111111111111111111111111111111111111111]\n    - argument 1: mocked 0x1111111111111111111111111111111111111111, called 0x2222222222222222222222222222222222222222"
+        ));
+        assert!(err.contains(
+            "    .withArgs([ethereum.Value.fromAddress(Address.fromString(\"0x2222222222222222222222222222222222222222\"))])\n    .returns([ethereum.Value.fromUnsignedBigInt(BigInt.fromString(\"0\"))])"
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn unmocked_call_policies() {
+        let mut context = get_context();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let signature = "getReserves():(uint112,address,bool)";
+
+        let policy = string_ptr(&mut context, "revert");
+        context
+            .set_unmocked_call_policy(&GasCounter::new(), policy)
+            .expect("Couldn't call set_unmocked_call_policy.");
+        let result = ethereum_call(&mut context, contract, "getReserves", signature, &[]);
+        assert_eq!(result, None);
+
+        let policy = string_ptr(&mut context, "zero");
+        context
+            .set_unmocked_call_policy(&GasCounter::new(), policy)
+            .expect("Couldn't call set_unmocked_call_policy.");
+        let result = ethereum_call(&mut context, contract, "getReserves", signature, &[]);
+        assert_eq!(
+            result,
+            Some(vec![
+                Token::Uint(0u64.into()),
+                Token::Address(Address::zero()),
+                Token::Bool(false),
+            ])
+        );
+
+        let warning = context
+            .unmocked_calls_warning()
+            .expect("Unmocked calls should be listed.");
+        assert!(warning.starts_with("2 contract calls were not mocked:"));
+        assert!(warning.contains("getReserves():(uint112,address,bool)() -> reverted"));
+    }
+
+    #[test]
+    #[serial]
+    fn forward_unmocked_calls_to_rpc_and_replay_them() {
+        let mut context = get_context();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let owner = Token::Address(
+            Address::from_str("90cBa2Bbb19ecc291A12066Fd8329D65FA1f1947")
+                .expect("Couldn't create Address."),
+        );
+        let cache = std::env::temp_dir().join("matchstick_rpc_cache.json");
+        let _ = std::fs::remove_file(&cache);
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind listener.");
+        let endpoint = format!(
+            "http://{}",
+            listener.local_addr().expect("Couldn't get address.")
+        );
+        let server = std::thread::spawn(move || serve_eth_calls(listener, 2));
+
+        let check_calls = |context: &mut MatchstickInstanceContext<Chain>| {
+            assert_eq!(
+                ethereum_call(
+                    context,
+                    contract,
+                    "balanceOf",
+                    "balanceOf(address):(uint256)",
+                    &[owner.clone()],
+                ),
+                Some(vec![Token::Uint(42u64.into())])
+            );
+            assert_eq!(
+                ethereum_call(context, contract, "owner", "owner():(address)", &[]),
+                None
+            );
+        };
+
+        ETH_RPC
+            .with(|rpc| *rpc.borrow_mut() = Some(EthRpc::new(Some(endpoint), cache.clone(), None)));
+        check_calls(&mut context);
+        server.join().expect("Couldn't join server.");
+
+        // Without an endpoint, the recorded responses are replayed.
+        ETH_RPC.with(|rpc| *rpc.borrow_mut() = Some(EthRpc::new(None, cache.clone(), None)));
+        check_calls(&mut context);
+
+        let recorded: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(&cache).expect("Couldn't read the cache."),
+        )
+        .expect("Couldn't parse the cache.");
+        assert_eq!(recorded.as_array().map(|records| records.len()), Some(2));
+        assert!(context.calls.iter().all(|call| call.mocked));
+        assert_eq!(
+            context.calls[1].result,
+            CallOutcome::Reverted(Some(RevertData::Reason("Not the owner".to_owned())))
+        );
+
+        std::fs::remove_file(&cache).expect("Couldn't remove the cache.");
+    }
+
+    #[test]
+    #[serial]
+    fn mock_reverts_with_reason_custom_error_and_failure() {
+        let mut context = get_context();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let owner = Token::Address(
+            Address::from_str("90cBa2Bbb19ecc291A12066Fd8329D65FA1f1947")
+                .expect("Couldn't create Address."),
+        );
+
+        let (address, name, signature, args, _) = mocked_function_ptrs(
+            &mut context,
+            contract,
+            "withdraw",
+            "withdraw(uint256):()",
+            &[Token::Uint(1u64.into())],
+            &[],
+        );
+        let reason = string_ptr(&mut context, "Nothing to withdraw");
+        context
+            .mock_function_reverts_with_reason(
+                &GasCounter::new(),
+                address,
+                name,
+                signature,
+                args,
+                reason,
+            )
+            .expect("Couldn't call mock_function_reverts_with_reason.");
+
+        let error_args = [Token::Uint(5u64.into()), Token::Uint(10u64.into())];
+        let (address, name, signature, args, error_args) = mocked_function_ptrs(
+            &mut context,
+            contract,
+            "withdraw",
+            "withdraw(uint256):()",
+            &[Token::Uint(10u64.into())],
+            &error_args,
+        );
+        let error_signature = string_ptr(&mut context, "InsufficientBalance(uint256,uint256)");
+        context
+            .mock_function_reverts_with_error(
+                &GasCounter::new(),
+                address,
+                name,
+                signature,
+                args,
+                error_signature,
+                error_args,
+            )
+            .expect("Couldn't call mock_function_reverts_with_error.");
+
+        let (address, name, signature, args, _) = mocked_function_ptrs(
+            &mut context,
+            contract,
+            "balanceOf",
+            "balanceOf(address):(uint256)",
+            &[owner.clone()],
+            &[],
+        );
+        let message = string_ptr(&mut context, "node unavailable");
+        context
+            .mock_function_fails(&GasCounter::new(), address, name, signature, args, message)
+            .expect("Couldn't call mock_function_fails.");
+
+        for amount in [1u64, 10] {
+            let result = ethereum_call(
+                &mut context,
+                contract,
+                "withdraw",
+                "withdraw(uint256):()",
+                &[Token::Uint(amount.into())],
+            );
+            assert_eq!(result, None);
+        }
+        assert_eq!(
+            context.calls[0].result,
+            CallOutcome::Reverted(Some(RevertData::Reason("Nothing to withdraw".to_owned())))
+        );
+        let mut error_data = ethabi::short_signature(
+            "InsufficientBalance",
+            &[ethabi::ParamType::Uint(256), ethabi::ParamType::Uint(256)],
+        )
+        .to_vec();
+        error_data.extend(ethabi::encode(&[
+            Token::Uint(5u64.into()),
+            Token::Uint(10u64.into()),
+        ]));
+        assert_eq!(
+            context.calls[1].result,
+            CallOutcome::Reverted(Some(RevertData::Error(error_data)))
+        );
+        assert!(context.calls[0]
+            .describe()
+            .ends_with("-> reverted with \"Nothing to withdraw\""));
+
+        // A failing call doesn't return null like a revert, but fails the handler.
+        let call_ptr = contract_call_ptr(
+            &mut context,
+            contract,
+            "balanceOf",
+            "balanceOf(address):(uint256)",
+            &[owner],
+        );
+        assert!(context.ethereum_call(&GasCounter::new(), call_ptr).is_err());
+        assert_eq!(
+            context.calls[2].result,
+            CallOutcome::Failed("node unavailable".to_owned())
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn unused_mocks_report() {
+        let mut context = get_context();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let owner = Token::Address(
+            Address::from_str("90cBa2Bbb19ecc291A12066Fd8329D65FA1f1947")
+                .expect("Couldn't create Address."),
+        );
+
+        for fn_name in ["balanceOf", "allowance"] {
+            let signature = format!("{}(address):(uint256)", fn_name);
+            let (address, name, signature_ptr, args, return_value) = mocked_function_ptrs(
+                &mut context,
+                contract,
+                fn_name,
+                &signature,
+                &[owner.clone()],
+                &[Token::Uint(1u64.into())],
+            );
+            context
+                .mock_function(
+                    &GasCounter::new(),
+                    address,
+                    name,
+                    signature_ptr,
+                    args,
+                    return_value,
+                    AscPtr::new(0),
+                )
+                .expect("Couldn't call mock_function.");
+        }
+        mock_function_matching(
+            &mut context,
+            "*",
+            "decimals",
+            "decimals():(uint8)",
+            &[],
+            &[Token::Uint(18u64.into())],
+        );
+
+        ethereum_call(
+            &mut context,
+            contract,
+            "balanceOf",
+            "balanceOf(address):(uint256)",
+            &[owner.clone()],
+        );
+        let report = context
+            .unused_mocks_report()
+            .expect("Unused mocks should be listed.");
+        assert!(report.starts_with("2 mocks were never called:"));
+        assert!(report.contains("allowance(address):(uint256)(0x90cb"));
+        assert!(report.contains("*.decimals():(uint8) with []"));
+        assert!(!report.contains("balanceOf"));
+
+        ethereum_call(
+            &mut context,
+            contract,
+            "decimals",
+            "decimals():(uint8)",
+            &[],
+        );
+        ethereum_call(
+            &mut context,
+            contract,
+            "allowance",
+            "allowance(address):(uint256)",
+            &[owner],
+        );
+        assert_eq!(context.unused_mocks_report(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn mock_contract_function_from_manifest_abi() {
+        let mut context = get_context();
+        use_gravity_abi();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let owner = Token::Address(
+            Address::from_str("90cBa2Bbb19ecc291A12066Fd8329D65FA1f1947")
+                .expect("Couldn't create Address."),
+        );
+
+        for (fn_signature, return_value) in [
+            (
+                "getGravatar",
+                vec![
+                    Token::String("Gravatar".to_owned()),
+                    Token::String("https://example.com/gravatar.png".to_owned()),
+                ],
+            ),
+            ("gravatarCount(address)", vec![Token::Uint(3.into())]),
+        ] {
+            let contract_name = string_ptr(&mut context, "Gravity");
+            let (address, _, signature_ptr, args, return_value) = mocked_function_ptrs(
+                &mut context,
+                contract,
+                "",
+                fn_signature,
+                &[owner.clone()],
+                &return_value,
+            );
+            context
+                .mock_contract_function(
+                    &GasCounter::new(),
+                    contract_name,
+                    address,
+                    signature_ptr,
+                    args,
+                    return_value,
+                    AscPtr::new(0),
+                )
+                .expect("Couldn't call mock_contract_function.");
+        }
+
+        assert!(context.abis.contains_key("Gravity"));
+        assert_eq!(
+            ethereum_call(
+                &mut context,
+                contract,
+                "getGravatar",
+                "getGravatar(address):(string,string)",
+                &[owner.clone()],
+            ),
+            Some(vec![
+                Token::String("Gravatar".to_owned()),
+                Token::String("https://example.com/gravatar.png".to_owned()),
+            ])
+        );
+        assert_eq!(
+            ethereum_call(
+                &mut context,
+                contract,
+                "gravatarCount",
+                "gravatarCount(address):(uint256)",
+                &[owner],
+            ),
+            Some(vec![Token::Uint(3.into())])
+        );
+        assert_eq!(
+            context.signature_in_abis("gravatarCount():(uint256)"),
+            Some(true)
+        );
+        assert_eq!(
+            context.signature_in_abis("gravatarCount():(bool)"),
+            Some(false)
+        );
+
+        ABI_FILES.with(|files| files.borrow_mut().clear());
+    }
+
+    #[test]
+    #[serial]
+    fn mock_store_set_merges_partial_updates() {
+        let mut context = get_context();
+
+        let mut first = HashMap::new();
+        first.insert("id".to_owned(), Value::from("0x1"));
+        first.insert("displayName".to_owned(), Value::from("First"));
+        first.insert(
+            "imageUrl".to_owned(),
+            Value::from("https://example.com/1.png"),
+        );
+        let mut update = HashMap::new();
+        update.insert("displayName".to_owned(), Value::from("Updated"));
+        update.insert("imageUrl".to_owned(), Value::Null);
+
+        store_set(&mut context, "Gravatar", "0x1", first.clone());
+        store_set(&mut context, "Gravatar", "0x1", update.clone());
+
+        let gravatar = context
+            .entities()
+            .get("Gravatar")
+            .and_then(|gravatars| gravatars.get("0x1"))
+            .expect("No such key in map")
+            .clone();
+        assert_eq!(gravatar.get("id"), Some(&Value::from("0x1")));
+        assert_eq!(gravatar.get("displayName"), Some(&Value::from("Updated")));
+        assert_eq!(gravatar.get("imageUrl"), None);
+
+        context.merge_store_set = false;
+        store_set(&mut context, "Gravatar", "0x1", first);
+        store_set(&mut context, "Gravatar", "0x1", update.clone());
+
+        let gravatar = context
+            .entities()
+            .get("Gravatar")
+            .and_then(|gravatars| gravatars.get("0x1"))
+            .expect("No such key in map")
+            .clone();
+        assert_eq!(gravatar, update);
+    }
+
+    #[test]
+    #[serial]
+    fn entity_cache_follows_merge_store_set() {
+        let mut context = get_context();
+        context.enable_entity_cache(true);
+        context.merge_store_set = false;
+
+        let mut first = HashMap::new();
+        first.insert("id".to_owned(), Value::from("0x1"));
+        first.insert("displayName".to_owned(), Value::from("First"));
+        first.insert(
+            "imageUrl".to_owned(),
+            Value::from("https://example.com/1.png"),
+        );
+        let mut update = HashMap::new();
+        update.insert("id".to_owned(), Value::from("0x1"));
+        update.insert("displayName".to_owned(), Value::from("Updated"));
+
+        store_set(&mut context, "Gravatar", "0x1", first);
+        store_set(&mut context, "Gravatar", "0x1", update);
+
+        let cached = context
+            .cached_entity("Gravatar", "0x1")
+            .expect("Entity is not in the cache.");
+        assert_eq!(cached.get("displayName"), Some(&Value::from("Updated")));
+        assert_eq!(cached.get("imageUrl"), None);
+
+        let gravatar = context
+            .entities()
+            .get("Gravatar")
+            .and_then(|gravatars| gravatars.get("0x1"))
+            .expect("No such key in map")
+            .clone();
+        assert_eq!(gravatar.get("imageUrl"), None);
+    }
+
+    #[test]
+    #[serial]
+    fn merge_store_set_does_not_carry_over_to_the_next_test() {
+        let mut context = get_context();
+
+        // The first test replaces entities on `store.set`.
+        context.reset_test_state();
+        context.merge_store_set = false;
+
+        // The second test merges them again, as configured.
+        context.reset_test_state();
+        assert!(context.merge_store_set);
+    }
+
+    #[test]
+    #[serial]
+    fn entity_cache_merges_and_flushes_per_block() {
+        let mut context = get_context();
+        context.enable_entity_cache(true);
+
+        let mut first = HashMap::new();
+        first.insert("id".to_owned(), Value::from("0x1"));
+        first.insert("displayName".to_owned(), Value::from("First"));
+        first.insert(
+            "imageUrl".to_owned(),
+            Value::from("https://example.com/1.png"),
+        );
+        let mut update = HashMap::new();
+        update.insert("displayName".to_owned(), Value::from("Updated"));
+
+        store_set(&mut context, "Gravatar", "0x1", first);
+        store_set(&mut context, "Gravatar", "0x1", update.clone());
+
+        // Pending changes are seen by `store.get`, but are not in the store yet.
+        let cached = context
+            .cached_entity("Gravatar", "0x1")
+            .expect("Entity is not in the cache.");
+        assert_eq!(cached.get("displayName"), Some(&Value::from("Updated")));
+        assert_eq!(
+            cached.get("imageUrl"),
+            Some(&Value::from("https://example.com/1.png"))
+        );
+        assert!(context.store.entities().get("Gravatar").is_none());
+
+        // Any other read of the store writes them to it first, as a single change.
+        let gravatar_ptr = string_ptr(&mut context, "Gravatar");
+        let result = context
+            .assert_entity_count(&GasCounter::new(), gravatar_ptr, 1)
+            .expect("Couldn't call assert_entity_count.");
+        assert!(result);
+        assert_eq!(context.store.history().len(), 1);
+
+        let event_ptr = string_ptr(&mut context, "");
+        context
+            .mock_block(&GasCounter::new(), 1, event_ptr)
+            .expect("Couldn't call mock_block.");
+
+        // A set after a remove in the same block replaces the entity instead of merging.
+        let id_ptr = string_ptr(&mut context, "0x1");
+        context
+            .mock_store_remove(&GasCounter::new(), gravatar_ptr, id_ptr)
+            .expect("Couldn't call mock_store_remove.");
+        store_set(&mut context, "Gravatar", "0x1", update);
+        assert!(context
+            .store
+            .entities()
+            .get("Gravatar")
+            .and_then(|gravatars| gravatars.get("0x1"))
+            .map_or(false, |gravatar| gravatar.contains_key("imageUrl")));
+        context
+            .flush_entity_cache(&GasCounter::new())
+            .expect("Couldn't call flush_entity_cache.");
+
+        let gravatar = context
+            .entities()
+            .get("Gravatar")
+            .and_then(|gravatars| gravatars.get("0x1"))
+            .expect("No such key in map")
+            .clone();
+        assert_eq!(gravatar.get("displayName"), Some(&Value::from("Updated")));
+        assert_eq!(gravatar.get("imageUrl"), None);
+        assert_eq!(context.store.history().len(), 2);
+        assert_eq!(context.store.history()[1].block.number, 1);
+    }
+
+    #[test]
+    #[serial]
+    fn entity_cache_revert_to_current_block_keeps_pending_changes() {
+        let mut context = get_context();
+        context.enable_entity_cache(true);
+        let mut gravatar = HashMap::new();
+        gravatar.insert("id".to_owned(), Value::from("0x1"));
+        gravatar.insert("displayName".to_owned(), Value::from("First"));
+        let stored = |context: &MatchstickInstanceContext<Chain>| {
+            context
+                .entities()
+                .get("Gravatar")
+                .map_or(false, |gravatars| gravatars.contains_key("0x1"))
+        };
+
+        let event_ptr = string_ptr(&mut context, "");
+        context
+            .mock_block(&GasCounter::new(), 2, event_ptr)
+            .expect("Couldn't call mock_block.");
+        store_set(&mut context, "Gravatar", "0x1", gravatar);
+
+        context
+            .revert_to_block(&GasCounter::new(), 2)
+            .expect("Couldn't call revert_to_block.");
+        assert!(stored(&context));
+        assert_eq!(context.store.history().len(), 1);
+
+        context
+            .revert_to_block(&GasCounter::new(), 1)
+            .expect("Couldn't call revert_to_block.");
+        assert!(!stored(&context));
+    }
+
+    #[test]
+    #[serial]
+    fn entity_cache_derived_fields_see_pending_changes() {
+        let mut context = get_context();
+        context.enable_entity_cache(true);
+
+        let mut account = HashMap::new();
+        account.insert("id".to_owned(), Value::from("graphAccountId"));
+        store_set(&mut context, "GraphAccount", "graphAccountId", account);
+        let mut nst = HashMap::new();
+        nst.insert("id".to_owned(), Value::from("nstid"));
+        nst.insert("signer".to_owned(), Value::from("graphAccountId"));
+        store_set(&mut context, "NameSignalTransaction", "nstid", nst);
+
+        let account = context
+            .cached_entity("GraphAccount", "graphAccountId")
+            .expect("Entity is not in the cache.");
+        let account = context.with_derived_fields("GraphAccount", "graphAccountId", &account);
+        assert_eq!(
+            account.get("nameSignalTransactions"),
+            Some(&Value::List(vec![Value::String("nstid".to_owned())]))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn entity_cache_mode_does_not_carry_over_to_the_next_test() {
+        let mut context = get_context();
+        let mut gravatar = HashMap::new();
+        gravatar.insert("id".to_owned(), Value::from("0x1"));
+
+        // The first test turns the entity cache on and leaves a change pending.
+        context.reset_test_state();
+        context.enable_entity_cache(true);
+        store_set(&mut context, "Gravatar", "0x1", gravatar);
+
+        // The second test runs with the configured mode and sees the change in the store.
+        context.reset_test_state();
+        assert!(context.entity_cache.borrow().is_none());
+        assert!(context
+            .store
+            .entities()
+            .get("Gravatar")
+            .map_or(false, |gravatars| gravatars.contains_key("0x1")));
+    }
+
+    #[test]
+    #[serial]
+    fn mock_writable_store_transact_and_revert() {
+        let context = get_context();
+
+        // The store graph-node writes to is the one the host functions use.
+        let store = context.store.clone();
+        let deployment = DeploymentHash::new("matchstick").expect("Couldn't create deployment.");
+
+        // graph-node parses `schema.graphql` itself, `Bytes` and `BigInt` ids included.
+        let input_schema = store.input_schema();
+        assert_eq!(input_schema.id, deployment);
+        assert!(input_schema.document.to_string().contains("type Transfer"));
+        let key =
+            |id: &str| EntityKey::data(deployment.clone(), "Gravatar".to_owned(), id.to_owned());
+        let gravatar = |display_name: &str| {
+            let mut data = HashMap::new();
+            data.insert("displayName".to_owned(), Value::from(display_name));
+            Entity::from(data)
+        };
+        let block = |number: u64| BlockPtr::from((H256::from_low_u64_be(number), number));
+        let stopwatch = || {
+            StopwatchMetrics::new(
+                Logger::root(Discard, o!()),
+                deployment.clone(),
+                Arc::new(MockMetricsRegistry::new()),
+            )
+        };
+
+        store
+            .transact_block_operations(
+                block(1),
+                None,
+                vec![
+                    EntityModification::Insert {
+                        key: key("0x1"),
+                        data: gravatar("First"),
+                    },
+                    EntityModification::Insert {
+                        key: key("0x2"),
+                        data: gravatar("Second"),
+                    },
+                ],
+                stopwatch(),
+                vec![],
+                vec![],
+            )
+            .expect("Couldn't transact block 1.");
+        store
+            .transact_block_operations(
+                block(2),
+                None,
+                vec![
+                    EntityModification::Overwrite {
+                        key: key("0x1"),
+                        data: gravatar("Updated"),
+                    },
+                    EntityModification::Remove { key: key("0x2") },
+                ],
+                stopwatch(),
+                vec![],
+                vec![],
+            )
+            .expect("Couldn't transact block 2.");
+
+        assert_eq!(store.block_ptr(), Some(block(2)));
+        assert_eq!(store.get(&key("0x1")).unwrap(), Some(gravatar("Updated")));
+        assert_eq!(store.get(&key("0x2")).unwrap(), None);
+
+        store
+            .revert_block_operations(block(1), None)
+            .expect("Couldn't revert to block 1.");
+
+        let entity_type = EntityType::new("Gravatar".to_owned());
+        let mut ids_for_type = BTreeMap::new();
+        ids_for_type.insert(&entity_type, vec!["0x1", "0x2"]);
+        let entities = store.get_many(ids_for_type).unwrap();
+
+        assert_eq!(store.block_ptr(), Some(block(1)));
+        assert_eq!(entities.get(&entity_type).map(|e| e.len()), Some(2));
+        assert_eq!(store.get(&key("0x1")).unwrap(), Some(gravatar("First")));
+        assert_eq!(
+            context
+                .entities()
+                .get("Gravatar")
+                .and_then(|gravatars| gravatars.get("0x2"))
+                .and_then(|gravatar| gravatar.get("displayName")),
+            Some(&Value::from("Second"))
+        );
+        assert_eq!(context.store.history().len(), 2);
+    }
+
+    #[test]
+    #[serial]
+    fn load_fixture_basic_test() {
+        let mut context = get_context();
+
+        let file_name = asc_string_from_str("mocks/fixtures/gravatars.yaml");
+        let file_name_ptr = AscPtr::alloc_obj(file_name, &mut context.wasm_ctx, &GasCounter::new())
+            .expect("Couldn't create pointer.");
+
+        context
+            .load_fixture(&GasCounter::new(), file_name_ptr)
+            .expect("Couldn't call load_fixture.");
+
+        let gravatars = context
+            .entities()
+            .get("Gravatar")
+            .expect("No such key in map")
+            .clone();
+        assert_eq!(gravatars.len(), 2);
+
+        let gravatar = gravatars.get("0x1").expect("No such key in map");
+        assert_eq!(gravatar.get("id"), Some(&Value::from("0x1")));
+        assert_eq!(
+            gravatar.get("owner"),
+            Some(&Value::Bytes(
+                Bytes::from_str("0x89205a3a3b2a69de6dbf7f01ed13b2108b2c43e7").unwrap()
+            ))
+        );
+        assert_eq!(
+            gravatar.get("displayName"),
+            Some(&Value::from("First Gravatar"))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn load_fixture_typed_values() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/delegators.json"));
+
+        let delegator = context
+            .entities()
+            .get("Delegator")
+            .and_then(|delegators| delegators.get("0xdelegator"))
+            .expect("No such key in map")
+            .clone();
+        assert_eq!(delegator.get("account"), Some(&Value::from("0xaccount")));
+        assert_eq!(
+            delegator.get("totalStakedTokens"),
+            Some(&Value::BigInt(
+                BigInt::from_str("1000000000000000000").unwrap()
+            ))
+        );
+        assert_eq!(
+            delegator.get("totalUnstakedTokens"),
+            Some(&Value::BigInt(BigInt::from(0)))
+        );
+        assert_eq!(
+            delegator.get("totalRealizedRewards"),
+            Some(&Value::BigDecimal(BigDecimal::from_str("1.5").unwrap()))
+        );
+        assert_eq!(delegator.get("createdAt"), Some(&Value::Int(1)));
+        assert_eq!(delegator.get("defaultDisplayName"), Some(&Value::Null));
+
+        let account = context
+            .entities()
+            .get("GraphAccount")
+            .and_then(|accounts| accounts.get("0xaccount"))
+            .expect("No such key in map")
+            .clone();
+        assert_eq!(
+            account.get("operators"),
+            Some(&Value::List(vec![Value::from("0xoperator")]))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn dump_store_can_be_loaded_as_fixture() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/delegators.json"));
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/gravatars.yaml"));
+        let store = context.entities().clone();
+
+        let dump = context.dump_store();
+        assert!(dump.find("\"Delegator\"").unwrap() < dump.find("\"Gravatar\"").unwrap());
+
+        let dump_file = std::env::temp_dir().join("matchstick_store_dump.json");
+        std::fs::write(&dump_file, dump).expect("Couldn't write store dump.");
+
+        context.entities().clear();
+        context.insert_fixture(&dump_file);
+
+        assert_eq!(*context.entities(), store);
+    }
+
+    #[test]
+    #[serial]
+    #[should_panic(expected = "does not match the GraphQL schema")]
+    fn load_fixture_invalid_values() {
+        let mut context = get_context();
+
+        context.insert_fixture(&PathBuf::from("mocks/fixtures/invalid.yaml"));
+    }
+
+    #[test]
+    #[serial]
+    fn load_contract_mocks_basic_test() {
+        let mut context = get_context();
+        use_gravity_abi();
+        let contract = "89205A3A3b2A69De6Dbf7f01ED13B2108B2c43e7";
+        let owner = Address::from_str("90cBa2Bbb19ecc291A12066Fd8329D65FA1f1947")
+            .expect("Couldn't create Address.");
+
+        context.insert_contract_mocks(&PathBuf::from("mocks/fixtures/contract_mocks.yaml"));
+
+        assert_eq!(
+            ethereum_call(
+                &mut context,
+                contract,
+                "getGravatar",
+                "getGravatar(address):(string,string)",
+                &[Token::Address(owner)],
+            ),
+            Some(vec![
+                Token::String("Gravatar".to_owned()),
+                Token::String("https://example.com/gravatar.png".to_owned()),
+            ])
+        );
+        assert_eq!(
+            ethereum_call(
+                &mut context,
+                contract,
+                "getGravatar",
+                "getGravatar(address):(string,string)",
+                &[Token::Address(Address::zero())],
+            ),
+            None
+        );
+        let unknown_owner = Address::from_low_u64_be(1);
+        assert_eq!(
+            ethereum_call(
+                &mut context,
+                contract,
+                "getGravatar",
+                "getGravatar(address):(string,string)",
+                &[Token::Address(unknown_owner)],
+            ),
+            None
+        );
+        let mut error_data =
+            ethabi::short_signature("UnknownOwner", &[ethabi::ParamType::Address]).to_vec();
+        error_data.extend(ethabi::encode(&[Token::Address(unknown_owner)]));
+        assert_eq!(
+            context.calls.last().map(|call| call.result.clone()),
+            Some(CallOutcome::Reverted(Some(RevertData::Error(error_data))))
+        );
+        assert_eq!(
+            ethereum_call(
+                &mut context,
+                contract,
+                "gravatarCount",
+                "gravatarCount():(uint256)",
+                &[],
+            ),
+            Some(vec![Token::Uint(3.into())])
+        );
+        assert_eq!(
+            ethereum_call(
+                &mut context,
+                contract,
+                "gravatarInfo",
+                "gravatarInfo(uint256):((address,string))",
+                &[Token::Uint(
+                    Uint::from_dec_str("1000000000000000000000").expect("Couldn't parse Uint.")
+                )],
+            ),
+            Some(vec![Token::Tuple(vec![
+                Token::Address(owner),
+                Token::String("Gravatar".to_owned()),
+            ])])
+        );
+    }
+
+    #[test]
+    #[serial]
+    #[should_panic(expected = "do not match the ABIs of subgraph.yaml")]
+    fn load_contract_mocks_invalid_calls() {
+        let mut context = get_context();
+        use_gravity_abi();
+
+        context.insert_contract_mocks(&PathBuf::from("mocks/fixtures/invalid_contract_mocks.yaml"));
+    }
+
+    #[test]
+    #[serial]
+    fn mock_ipfs_file_basic_test() {
+        let mut context = get_context();
+
+        assert_eq!(context.ipfs.len(), 0);
+
+        let hash = asc_string_from_str("QmTkzDwWqPbnAh5YiV5VwcTLnGdwSNsNTn2aDxdXBFca7D");
+        let file = asc_string_from_str("./mocks/ipfs.json");
+        let hash_ptr = AscPtr::alloc_obj(hash, &mut context.wasm_ctx, &GasCounter::new())
+            .expect("Couldn't create pointer.");
         let file_ptr = AscPtr::alloc_obj(file, &mut context.wasm_ctx, &GasCounter::new())
             .expect("Couldn't create pointer.");
 
@@ -1218,7 +3458,7 @@ mod unit_tests {
             .mock_ipfs_file(&GasCounter::new(), hash_ptr, file_ptr)
             .unwrap();
 
-        assert_eq!(context.store.len(), 0);
+        assert_eq!(context.entities().len(), 0);
 
         context
             .mock_ipfs_map(
@@ -1230,9 +3470,13 @@ mod unit_tests {
             )
             .unwrap();
 
-        assert_eq!(context.store.len(), 1);
+        assert_eq!(context.entities().len(), 1);
 
-        let gravatar_map = context.store.get("Gravatar").expect("No such key in map");
+        let gravatar_map = context
+            .entities()
+            .get("Gravatar")
+            .expect("No such key in map")
+            .clone();
 
         assert_eq!(gravatar_map.len(), 3);
 
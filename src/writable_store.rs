@@ -1,4 +1,5 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Mutex, MutexGuard};
 
 use async_trait::async_trait;
 use graph::{
@@ -7,33 +8,227 @@ use graph::{
     data::subgraph::schema::{SubgraphError, SubgraphHealth},
     prelude::*,
 };
+use lazy_static::lazy_static;
 
-pub struct MockWritableStore {}
+use crate::context::{EntityChange, MockBlock};
+use crate::logging;
+
+/// Entities by entity type and id.
+pub(crate) type EntityStore = HashMap<String, HashMap<String, HashMap<String, Value>>>;
+
+lazy_static! {
+    static ref DEPLOYMENT: DeploymentHash = DeploymentHash::new("matchstick")
+        .unwrap_or_else(|err| logging::critical!("Could not create deployment id: {}", err));
+    /// The schema from `schema.graphql` the way graph-node parses it, only parsed
+    /// when graph-node code asks the store for it.
+    static ref INPUT_SCHEMA: Arc<Schema> = {
+        let schema = Schema::parse(&crate::context::SCHEMA.to_string(), DEPLOYMENT.clone()).unwrap_or_else(|err| {
+            logging::critical!(
+                "Something went wrong when trying to parse `schema.graphql`: {}",
+                err
+            )
+        });
+
+        Arc::new(schema)
+    };
+}
+
+#[derive(Default)]
+struct State {
+    block_ptr: Option<BlockPtr>,
+    block_cursor: Option<String>,
+    data_sources: Vec<StoredDynamicDataSource>,
+    errors: Vec<SubgraphError>,
+}
+
+/// The mock store, holding the entities of `MatchstickInstanceContext` and every change
+/// made to them. It is a `WritableStore` as well, so graph-node code going through the
+/// store traits reads and writes the same entities as the host functions.
+#[derive(Default)]
+pub struct MockWritableStore {
+    entities: Mutex<EntityStore>,
+    /// Every `store.set` and `store.remove` since the store was last cleared, oldest first.
+    history: Mutex<Vec<EntityChange>>,
+    state: Mutex<State>,
+}
+
+/// The key graph-node knows an entity of the mocked subgraph by.
+pub(crate) fn entity_key(entity_type: &str, id: &str) -> EntityKey {
+    EntityKey::data(DEPLOYMENT.clone(), entity_type.to_owned(), id.to_owned())
+}
+
+fn to_entity(data: &HashMap<String, Value>) -> Entity {
+    Entity::from(data.clone())
+}
+
+pub(crate) fn from_entity(entity: &Entity) -> HashMap<String, Value> {
+    entity
+        .iter()
+        .map(|(field, value)| (field.clone(), value.clone()))
+        .collect()
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|err| logging::critical!("The store is poisoned: {}", err))
+}
+
+impl MockWritableStore {
+    pub fn new() -> Self {
+        MockWritableStore::default()
+    }
+
+    /// The entities by type and id. Changes made through it are not recorded.
+    pub(crate) fn entities(&self) -> MutexGuard<'_, EntityStore> {
+        lock(&self.entities)
+    }
+
+    pub(crate) fn history(&self) -> MutexGuard<'_, Vec<EntityChange>> {
+        lock(&self.history)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+
+    /// Sets or removes an entity, recording the change at the passed block.
+    pub(crate) fn write_entity(
+        &self,
+        block: &MockBlock,
+        entity_type: &str,
+        id: &str,
+        current: Option<HashMap<String, Value>>,
+    ) {
+        let mut entities = self.entities();
+        let previous = match &current {
+            Some(entity) => entities
+                .entry(entity_type.to_owned())
+                .or_default()
+                .insert(id.to_owned(), entity.clone()),
+            None => entities
+                .get_mut(entity_type)
+                .and_then(|entities| entities.remove(id)),
+        };
+
+        self.history().push(EntityChange {
+            block: block.clone(),
+            entity_type: entity_type.to_owned(),
+            id: id.to_owned(),
+            previous,
+            current,
+        });
+    }
+
+    /// Applies the changes graph-node made to the entities of a block.
+    pub(crate) fn apply_modifications(&self, block: &MockBlock, mods: Vec<EntityModification>) {
+        for modification in mods {
+            let (key, current) = match modification {
+                EntityModification::Insert { key, data }
+                | EntityModification::Overwrite { key, data } => (key, Some(from_entity(&data))),
+                EntityModification::Remove { key } => (key, None),
+            };
+            self.write_entity(block, key.entity_type.as_str(), &key.entity_id, current);
+        }
+    }
+
+    /// Undoes every change recorded at a later block than the passed one. Blocks can be
+    /// mocked in any order, so the changes are not assumed to be sorted by block: each
+    /// reverted entity gets the version left by its last kept change, or the one it had
+    /// before it was first changed.
+    pub(crate) fn revert_to_block(&self, block_number: u32) {
+        let mut entities = self.entities();
+        let mut history = self.history();
+
+        let reverted: BTreeSet<(String, String)> = history
+            .iter()
+            .filter(|change| change.block.number > block_number)
+            .map(|change| (change.entity_type.clone(), change.id.clone()))
+            .collect();
+        if reverted.is_empty() {
+            return;
+        }
+
+        // The version of every reverted entity, before its first change.
+        let mut versions: HashMap<(String, String), Option<HashMap<String, Value>>> =
+            HashMap::new();
+        for change in history.iter() {
+            let key = (change.entity_type.clone(), change.id.clone());
+            if reverted.contains(&key) && !versions.contains_key(&key) {
+                versions.insert(key, change.previous.clone());
+            }
+        }
+
+        history.retain(|change| change.block.number <= block_number);
+        // The kept changes are replayed, so their previous versions don't refer to reverted ones.
+        for change in history.iter_mut() {
+            if let Some(version) =
+                versions.get_mut(&(change.entity_type.clone(), change.id.clone()))
+            {
+                change.previous = version.clone();
+                *version = change.current.clone();
+            }
+        }
+
+        for ((entity_type, id), version) in versions {
+            let entities = entities.entry(entity_type).or_default();
+            match version {
+                Some(entity) => entities.insert(id, entity),
+                None => entities.remove(&id),
+            };
+        }
+    }
+
+    /// Removes all entities, their history and what graph-node stored about the subgraph.
+    pub(crate) fn clear(&self) {
+        self.entities().clear();
+        self.history().clear();
+        *self.state() = State::default();
+    }
+}
+
+/// The mocked block with the number of a block graph-node works with.
+fn mock_block(block_ptr: &BlockPtr) -> MockBlock {
+    MockBlock {
+        number: u32::try_from(block_ptr.number).unwrap_or_default(),
+        event: None,
+    }
+}
 
 #[async_trait]
 impl WritableStore for MockWritableStore {
     fn block_ptr(&self) -> Option<BlockPtr> {
-        unreachable!()
+        self.state().block_ptr.clone()
     }
 
     fn block_cursor(&self) -> Option<String> {
-        unreachable!()
+        self.state().block_cursor.clone()
     }
 
     fn start_subgraph_deployment(&self, _logger: &Logger) -> Result<(), StoreError> {
-        unreachable!()
+        Ok(())
     }
 
     fn revert_block_operations(
         &self,
-        _block_ptr_to: BlockPtr,
-        _firehose_cursor: Option<&str>,
+        block_ptr_to: BlockPtr,
+        firehose_cursor: Option<&str>,
     ) -> Result<(), StoreError> {
-        unreachable!()
+        self.revert_to_block(mock_block(&block_ptr_to).number);
+
+        let mut state = self.state();
+        state.data_sources.retain(|data_source| {
+            data_source
+                .creation_block
+                .map_or(true, |block| block <= block_ptr_to.number)
+        });
+        state.block_ptr = Some(block_ptr_to);
+        state.block_cursor = firehose_cursor.map(str::to_owned);
+        Ok(())
     }
 
     fn input_schema(&self) -> Arc<Schema> {
-        unreachable!()
+        INPUT_SCHEMA.clone()
     }
 
     fn unfail_deterministic_error(
@@ -41,65 +236,100 @@ impl WritableStore for MockWritableStore {
         _current_ptr: &BlockPtr,
         _parent_ptr: &BlockPtr,
     ) -> Result<(), StoreError> {
-        unreachable!()
+        self.state().errors.clear();
+        Ok(())
     }
 
     fn unfail_non_deterministic_error(&self, _current_ptr: &BlockPtr) -> Result<(), StoreError> {
-        unreachable!()
+        self.state().errors.clear();
+        Ok(())
     }
 
-    async fn fail_subgraph(&self, _error: SubgraphError) -> Result<(), StoreError> {
-        unreachable!()
+    async fn fail_subgraph(&self, error: SubgraphError) -> Result<(), StoreError> {
+        self.state().errors.push(error);
+        Ok(())
     }
 
     async fn supports_proof_of_indexing(&self) -> Result<bool, StoreError> {
-        unreachable!()
+        Ok(false)
     }
 
-    fn get(&self, _key: &EntityKey) -> Result<Option<Entity>, StoreError> {
-        unreachable!()
+    fn get(&self, key: &EntityKey) -> Result<Option<Entity>, StoreError> {
+        Ok(self
+            .entities()
+            .get(key.entity_type.as_str())
+            .and_then(|entities| entities.get(&key.entity_id))
+            .map(to_entity))
     }
 
     fn transact_block_operations(
         &self,
-        _block_ptr_to: BlockPtr,
-        _firehose_cursor: Option<String>,
-        _mods: Vec<EntityModification>,
+        block_ptr_to: BlockPtr,
+        firehose_cursor: Option<String>,
+        mods: Vec<EntityModification>,
         _stopwatch: StopwatchMetrics,
-        _data_sources: Vec<StoredDynamicDataSource>,
-        _deterministic_errors: Vec<SubgraphError>,
+        data_sources: Vec<StoredDynamicDataSource>,
+        deterministic_errors: Vec<SubgraphError>,
     ) -> Result<(), StoreError> {
-        unreachable!()
+        self.apply_modifications(&mock_block(&block_ptr_to), mods);
+
+        let mut state = self.state();
+        state.data_sources.extend(data_sources);
+        state.errors.extend(deterministic_errors);
+        state.block_ptr = Some(block_ptr_to);
+        state.block_cursor = firehose_cursor;
+        Ok(())
     }
 
     fn get_many(
         &self,
-        _ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
+        ids_for_type: BTreeMap<&EntityType, Vec<&str>>,
     ) -> Result<BTreeMap<EntityType, Vec<Entity>>, StoreError> {
-        unreachable!()
+        let entities = self.entities();
+
+        Ok(ids_for_type
+            .into_iter()
+            .map(|(entity_type, ids)| {
+                let found: Vec<Entity> = entities
+                    .get(entity_type.as_str())
+                    .map(|entities| {
+                        ids.iter()
+                            .filter_map(|id| entities.get(*id))
+                            .map(to_entity)
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                (entity_type.clone(), found)
+            })
+            .filter(|(_, found)| !found.is_empty())
+            .collect())
     }
 
     fn deployment_synced(&self) -> Result<(), StoreError> {
-        unreachable!()
+        Ok(())
     }
 
     async fn is_deployment_synced(&self) -> Result<bool, StoreError> {
-        unreachable!()
+        Ok(true)
     }
 
     fn unassign_subgraph(&self) -> Result<(), StoreError> {
-        unreachable!()
+        Ok(())
     }
 
     async fn load_dynamic_data_sources(&self) -> Result<Vec<StoredDynamicDataSource>, StoreError> {
-        unreachable!()
+        Ok(self.state().data_sources.clone())
     }
 
     fn shard(&self) -> &str {
-        unreachable!()
+        "primary"
     }
 
     async fn health(&self, _id: &DeploymentHash) -> Result<SubgraphHealth, StoreError> {
-        unreachable!()
+        if self.state().errors.is_empty() {
+            Ok(SubgraphHealth::Healthy)
+        } else {
+            Ok(SubgraphHealth::Failed)
+        }
     }
 }